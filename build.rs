@@ -0,0 +1,43 @@
+//! Assembles the standard library modules into object files embedded
+//! by `stdlib`, so that a module which doesn't assemble fails the build.
+
+// Only the assembler and the object format are used out of the shared
+// modules, which are private here rather than exported as in the crate
+#![allow(dead_code, unused_imports, clippy::upper_case_acronyms)]
+
+#[path = "src/assembler/mod.rs"]
+mod assembler;
+#[path = "src/bytecode.rs"]
+mod bytecode;
+#[path = "src/instruction.rs"]
+mod instruction;
+
+use assembler::Assembler;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Names of the library modules, sources are `src/stdlib/<name>.iasm`.
+const MODULES: [&str; 5] = ["mem", "alloc", "string", "math", "io"];
+
+fn main() {
+    let out_dir = PathBuf::from(env::var_os("OUT_DIR").expect("OUT_DIR is set by cargo"));
+    for dependency in &["src/assembler", "src/bytecode.rs", "src/instruction.rs"] {
+        println!("cargo:rerun-if-changed={}", dependency);
+    }
+    for name in &MODULES {
+        let path = format!("src/stdlib/{}.iasm", name);
+        println!("cargo:rerun-if-changed={}", path);
+        let source = fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path, e));
+        let mut object = Assembler::new()
+            .with_base_dir(Path::new("src/stdlib"))
+            .assemble_object(&source)
+            .unwrap_or_else(|diagnostics| panic!("\n{}", diagnostics.with_file(&path)));
+        object
+            .debug_info
+            .set_main_file(&format!("stdlib/{}.iasm", name));
+        let output = out_dir.join(format!("{}.iob", name));
+        fs::write(&output, object.to_bytes())
+            .unwrap_or_else(|e| panic!("{}: {}", output.display(), e));
+    }
+}
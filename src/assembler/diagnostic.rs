@@ -0,0 +1,310 @@
+use crate::assembler::{
+    parsing::{directive, label_decl, operand, parse_integer, ParsingError},
+    AssemblerError,
+};
+use crate::instruction::{Opcode, MAX_OPERANDS};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::num::IntErrorKind;
+
+/// Location of a piece of source text.
+///
+/// Lines and columns start from 1, columns are counted in characters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Length of the text, in characters.
+    pub len: usize,
+}
+
+impl Span {
+    /// Returns a span of `len` bytes starting at the byte `offset` of `source`.
+    pub fn new(source: &str, offset: usize, len: usize) -> Span {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        Span {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            len: source[offset..offset + len].chars().count(),
+        }
+    }
+}
+
+/// Assembler error along with the place in the source it refers to.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub error: AssemblerError,
+    pub span: Span,
+    pub file: Option<String>,
+    /// Text of the line the span starts on.
+    pub source_line: String,
+    /// Related places, such as the macro calls the error comes from.
+    pub notes: Vec<Note>,
+    /// Line and column in the expanded source, diagnostics are ordered by.
+    pub(super) position: (usize, usize),
+}
+
+impl Diagnostic {
+    pub fn new(error: AssemblerError, source: &str, span: Span) -> Diagnostic {
+        Diagnostic {
+            error,
+            span,
+            file: None,
+            source_line: line_of(source, span),
+            notes: vec![],
+            position: (span.line, span.column),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let file = self.file.as_deref().unwrap_or("<input>");
+        writeln!(f, "error: {}", self.error)?;
+        render_excerpt(f, file, self.span, &self.source_line)?;
+        for note in &self.notes {
+            let file = note.file.as_deref().unwrap_or("<input>");
+            writeln!(f, "\nnote: {}", note.message)?;
+            render_excerpt(f, file, note.span, &note.source_line)?;
+        }
+        Ok(())
+    }
+}
+
+/// Secondary place in the source attached to a diagnostic.
+#[derive(Debug, Clone)]
+pub struct Note {
+    pub message: String,
+    pub span: Span,
+    pub file: Option<String>,
+    pub source_line: String,
+}
+
+impl Note {
+    pub fn new(message: String, source: &str, span: Span) -> Note {
+        Note {
+            message,
+            span,
+            file: None,
+            source_line: line_of(source, span),
+        }
+    }
+}
+
+fn line_of(source: &str, span: Span) -> String {
+    let line = source.lines().nth(span.line - 1).unwrap_or_default();
+    line.to_string()
+}
+
+/// Writes the location and the source line with the span underlined.
+fn render_excerpt(
+    f: &mut fmt::Formatter,
+    file: &str,
+    span: Span,
+    source_line: &str,
+) -> fmt::Result {
+    let Span { line, column, len } = span;
+    let gutter = " ".repeat(line.to_string().len());
+    // Keep tabs, so that the caret lines up with the excerpt
+    let indent: String = source_line
+        .chars()
+        .take(column - 1)
+        .map(|c| if c == '\t' { '\t' } else { ' ' })
+        .collect();
+    writeln!(f, "{}--> {}:{}:{}", gutter, file, line, column)?;
+    writeln!(f, "{} |", gutter)?;
+    writeln!(f, "{} | {}", line, source_line)?;
+    write!(f, "{} | {}{}", gutter, indent, "^".repeat(len.max(1)))
+}
+
+/// All errors found while assembling a source.
+#[derive(Debug, Clone, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// Orders the diagnostics by their position in the source.
+    pub fn sort(&mut self) {
+        self.diagnostics.sort_by_key(|d| d.position);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// Sets the file name of the diagnostics and notes which don't have one yet.
+    pub fn with_file(mut self, file: &str) -> Diagnostics {
+        for diagnostic in &mut self.diagnostics {
+            diagnostic.file.get_or_insert_with(|| file.to_string());
+            for note in &mut diagnostic.notes {
+                note.file.get_or_insert_with(|| file.to_string());
+            }
+        }
+        self
+    }
+}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}\n", diagnostic)?;
+        }
+        write!(f, "{} error(s) found", self.diagnostics.len())
+    }
+}
+
+impl Error for Diagnostics {}
+
+/// Finds out why a line could not be parsed.
+///
+/// Returns the error with the byte offset and length of the offending
+/// part of the line.
+pub(super) fn diagnose(line: &str) -> (AssemblerError, usize, usize) {
+    let code = line.split(';').next().unwrap_or_default().trim_end();
+    let fallback = |word: &str, offset| {
+        let error = AssemblerError::UnparsedInput(word.to_string());
+        (error, offset, word.len())
+    };
+    let mut words = code
+        .split_whitespace()
+        .map(|word| (word.as_ptr() as usize - code.as_ptr() as usize, word));
+
+    let mut head = words.next();
+    if let Some((_, word)) = head {
+        if matches!(label_decl(word), Ok(("", _))) {
+            head = words.next();
+        }
+    }
+    let (offset, head) = match head {
+        Some(head) => head,
+        None => return fallback(code, 0),
+    };
+    let is_directive = head.starts_with('.');
+    let is_word = head.chars().all(|c| c.is_ascii_alphanumeric());
+    if (is_directive && !matches!(directive(head), Ok(("", _)))) || (!is_directive && !is_word) {
+        return fallback(head, offset);
+    }
+    if !is_directive && Opcode::from(head) == Opcode::IGL {
+        let error = ParsingError::UnknownOpcode(head.to_string());
+        return (error.into(), offset, head.len());
+    }
+
+    let mut found = 0;
+    for (offset, word) in words {
+        if word.starts_with('"') {
+            // Strings may contain spaces, so words can't be checked further
+            return fallback(&code[offset..], offset);
+        }
+        if !matches!(operand(word), Ok(("", _))) {
+            let rest = word.get(1..).unwrap_or_default();
+            let digits = !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit());
+            let overflow = matches!(
+                parse_integer(rest),
+                Err(IntErrorKind::PosOverflow) | Err(IntErrorKind::NegOverflow)
+            );
+            let error = match word.chars().next() {
+                Some('$') if digits => ParsingError::InvalidRegister(word.to_string()),
+                Some('#') if overflow => ParsingError::NumberOutOfRange(word.to_string()),
+                _ => return fallback(word, offset),
+            };
+            return (error.into(), offset, word.len());
+        }
+        found += 1;
+    }
+    if !is_directive && found > MAX_OPERANDS {
+        let expected = Opcode::overloads(head)
+            .next()
+            .map_or(0, |info| info.operands.len());
+        let error = ParsingError::WrongOperandCount {
+            mnemonic: head.to_string(),
+            expected,
+            found,
+        };
+        return (error.into(), 0, code.len());
+    }
+    fallback(code, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_span() {
+        let source = "hlt\n  load $0 #1\n";
+        let span = Span::new(source, 6, 4);
+        assert_eq!(
+            span,
+            Span {
+                line: 2,
+                column: 3,
+                len: 4
+            }
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let source = "hlt\n\tdaol $1\n";
+        let span = Span::new(source, 5, 4);
+        let error = ParsingError::UnknownOpcode("daol".to_string()).into();
+        let mut diagnostic = Diagnostic::new(error, source, span);
+        diagnostic.file = Some("prog.iasm".to_string());
+        assert_eq!(
+            diagnostic.to_string(),
+            "error: Unknown opcode: daol\n --> prog.iasm:2:2\n  |\n2 | \tdaol $1\n  | \t^^^^"
+        );
+    }
+
+    #[test]
+    fn test_diagnose() {
+        let (error, offset, len) = diagnose("loop: daol $1 ; comment");
+        assert!(matches!(
+            error,
+            AssemblerError::Parsing(ParsingError::UnknownOpcode(_))
+        ));
+        assert_eq!((offset, len), (6, 4));
+
+        let (error, offset, len) = diagnose("load $40 #1");
+        assert!(matches!(
+            error,
+            AssemblerError::Parsing(ParsingError::InvalidRegister(_))
+        ));
+        assert_eq!((offset, len), (5, 3));
+
+        let (error, offset, _) = diagnose("load $0 #0x1_0000_0000");
+        assert!(matches!(
+            error,
+            AssemblerError::Parsing(ParsingError::NumberOutOfRange(_))
+        ));
+        assert_eq!(offset, 8);
+
+        let (error, _, _) = diagnose("add $0 $1 $2 $3");
+        assert!(matches!(
+            error,
+            AssemblerError::Parsing(ParsingError::WrongOperandCount {
+                expected: 3,
+                found: 4,
+                ..
+            })
+        ));
+
+        let (error, offset, len) = diagnose("load $0 %1");
+        assert!(matches!(error, AssemblerError::UnparsedInput(_)));
+        assert_eq!((offset, len), (8, 2));
+    }
+}
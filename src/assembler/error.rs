@@ -0,0 +1,63 @@
+use crate::assembler::{parsing::ParsingError, preprocessor::MacroError};
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone)]
+pub enum AssemblerError {
+    /// Source could not be parsed, contains the offending text.
+    UnparsedInput(String),
+    DuplicateLabel(String),
+    /// Instruction or data declaration placed in the wrong section.
+    WrongSection(String),
+    Parsing(ParsingError),
+    Macro(MacroError),
+    /// `.import` in a source assembled into an executable.
+    UnresolvedImport(String),
+    /// File named by an `.include` directive could not be read.
+    Include {
+        path: String,
+        reason: String,
+    },
+}
+
+impl Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssemblerError::UnparsedInput(text) => {
+                write!(f, "Unable to parse input near: {}", text)
+            }
+            AssemblerError::DuplicateLabel(name) => {
+                write!(f, "Label declared more than once: {}", name)
+            }
+            AssemblerError::WrongSection(instr) => {
+                write!(f, "Not allowed in the current section: {}", instr)
+            }
+            AssemblerError::Parsing(e) => write!(f, "{}", e),
+            AssemblerError::Macro(e) => write!(f, "{}", e),
+            AssemblerError::UnresolvedImport(name) => {
+                write!(
+                    f,
+                    "Imported label {} can only be resolved by the linker",
+                    name
+                )
+            }
+            AssemblerError::Include { path, reason } => {
+                write!(f, "Unable to include {}: {}", path, reason)
+            }
+        }
+    }
+}
+
+impl Error for AssemblerError {}
+
+impl From<ParsingError> for AssemblerError {
+    fn from(e: ParsingError) -> Self {
+        AssemblerError::Parsing(e)
+    }
+}
+
+impl From<MacroError> for AssemblerError {
+    fn from(e: MacroError) -> Self {
+        AssemblerError::Macro(e)
+    }
+}
@@ -0,0 +1,225 @@
+use crate::assembler::{symbols::SymbolTable, token::TokenError};
+use crate::bytecode::RelocationTarget;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Shl,
+    Shr,
+    And,
+    Xor,
+    Or,
+}
+
+impl BinaryOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Rem => "%",
+            BinaryOp::Shl => "<<",
+            BinaryOp::Shr => ">>",
+            BinaryOp::And => "&",
+            BinaryOp::Xor => "^",
+            BinaryOp::Or => "|",
+        }
+    }
+
+    fn apply(&self, lhs: i64, rhs: i64) -> Option<i64> {
+        match self {
+            BinaryOp::Add => lhs.checked_add(rhs),
+            BinaryOp::Sub => lhs.checked_sub(rhs),
+            BinaryOp::Mul => lhs.checked_mul(rhs),
+            BinaryOp::Div => lhs.checked_div(rhs),
+            BinaryOp::Rem => lhs.checked_rem(rhs),
+            BinaryOp::Shl if (0..64).contains(&rhs) => lhs.checked_shl(rhs as u32),
+            BinaryOp::Shr if (0..64).contains(&rhs) => lhs.checked_shr(rhs as u32),
+            BinaryOp::Shl | BinaryOp::Shr => None,
+            BinaryOp::And => Some(lhs & rhs),
+            BinaryOp::Xor => Some(lhs ^ rhs),
+            BinaryOp::Or => Some(lhs | rhs),
+        }
+    }
+}
+
+impl From<&str> for BinaryOp {
+    fn from(symbol: &str) -> Self {
+        match symbol {
+            "+" => BinaryOp::Add,
+            "-" => BinaryOp::Sub,
+            "*" => BinaryOp::Mul,
+            "/" => BinaryOp::Div,
+            "%" => BinaryOp::Rem,
+            "<<" => BinaryOp::Shl,
+            ">>" => BinaryOp::Shr,
+            "&" => BinaryOp::And,
+            "^" => BinaryOp::Xor,
+            "|" => BinaryOp::Or,
+            _ => unreachable!("unknown operator {}", symbol),
+        }
+    }
+}
+
+/// Constant expression evaluated at assembly time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    /// Label or constant defined with `.equ`.
+    Symbol(String),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary(op, Box::new(lhs), Box::new(rhs))
+    }
+
+    /// Computes the value of the expression, looking symbols up in the table.
+    ///
+    /// Arithmetic is done on 64-bit integers, overflows and division
+    /// by zero are reported as errors.
+    pub fn evaluate(&self, symbols: &SymbolTable) -> Result<i64, TokenError> {
+        let value = match self {
+            Expr::Number(value) => Some(*value),
+            Expr::Symbol(name) => {
+                let value = symbols.value(name);
+                Some(value.ok_or_else(|| TokenError::UndefinedLabel(name.clone()))?)
+            }
+            Expr::Neg(expr) => expr.evaluate(symbols)?.checked_neg(),
+            Expr::Not(expr) => Some(!expr.evaluate(symbols)?),
+            Expr::Binary(op, lhs, rhs) => op.apply(lhs.evaluate(symbols)?, rhs.evaluate(symbols)?),
+        };
+        value.ok_or_else(|| TokenError::InvalidExpression(self.to_string()))
+    }
+
+    /// Finds out which address the value depends on once the object
+    /// is linked, `None` means the value is absolute.
+    ///
+    /// Only a label plus or minus an absolute value can be relocated,
+    /// a difference of labels from the same section is absolute.
+    pub fn relocation(
+        &self,
+        symbols: &SymbolTable,
+    ) -> Result<Option<RelocationTarget>, TokenError> {
+        let not_relocatable = || TokenError::NotRelocatable(self.to_string());
+        let target = match self {
+            Expr::Number(_) => None,
+            Expr::Symbol(name) if symbols.is_import(name) => {
+                Some(RelocationTarget::Symbol(name.clone()))
+            }
+            Expr::Symbol(name) => symbols.section(name).map(RelocationTarget::Section),
+            Expr::Neg(expr) | Expr::Not(expr) => match expr.relocation(symbols)? {
+                Some(_) => return Err(not_relocatable()),
+                None => None,
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                match (op, lhs.relocation(symbols)?, rhs.relocation(symbols)?) {
+                    (_, None, None) => None,
+                    (BinaryOp::Add, Some(target), None)
+                    | (BinaryOp::Add, None, Some(target))
+                    | (BinaryOp::Sub, Some(target), None) => Some(target),
+                    (
+                        BinaryOp::Sub,
+                        Some(RelocationTarget::Section(lhs)),
+                        Some(RelocationTarget::Section(rhs)),
+                    ) if lhs == rhs => None,
+                    _ => return Err(not_relocatable()),
+                }
+            }
+        };
+        Ok(target)
+    }
+}
+
+impl Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Number(value) => write!(f, "{}", value),
+            Expr::Symbol(name) => write!(f, "{}", name),
+            Expr::Neg(expr) => write!(f, "-{}", expr),
+            Expr::Not(expr) => write!(f, "~{}", expr),
+            Expr::Binary(op, lhs, rhs) => write!(f, "({} {} {})", lhs, op.symbol(), rhs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::SectionKind;
+
+    #[test]
+    fn test_evaluate() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("table", SectionKind::ReadOnlyData, 8);
+        symbols.add_constant("SIZE", 4);
+
+        let expr = Expr::binary(
+            BinaryOp::Add,
+            Expr::Symbol("table".to_string()),
+            Expr::binary(
+                BinaryOp::Mul,
+                Expr::Symbol("SIZE".to_string()),
+                Expr::Neg(Box::new(Expr::Number(2))),
+            ),
+        );
+        assert_eq!(expr.evaluate(&symbols).unwrap(), 0);
+        assert_eq!(expr.to_string(), "(table + (SIZE * -2))");
+
+        let expr = Expr::binary(BinaryOp::Div, Expr::Number(1), Expr::Number(0));
+        assert!(matches!(
+            expr.evaluate(&symbols),
+            Err(TokenError::InvalidExpression(_))
+        ));
+        let expr = Expr::binary(BinaryOp::Shl, Expr::Number(1), Expr::Number(64));
+        assert!(expr.evaluate(&symbols).is_err());
+        let expr = Expr::Symbol("missing".to_string());
+        assert!(matches!(
+            expr.evaluate(&symbols),
+            Err(TokenError::UndefinedLabel(_))
+        ));
+    }
+
+    #[test]
+    fn test_relocation() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("start", SectionKind::Code, 0);
+        symbols.add_symbol("end", SectionKind::Code, 8);
+        symbols.add_symbol("table", SectionKind::ReadOnlyData, 8);
+        symbols.add_constant("SIZE", 4);
+        symbols.add_import("print");
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+
+        let expr = Expr::binary(BinaryOp::Add, symbol("SIZE"), symbol("table"));
+        assert_eq!(
+            expr.relocation(&symbols).unwrap(),
+            Some(RelocationTarget::Section(SectionKind::ReadOnlyData))
+        );
+        let expr = Expr::binary(BinaryOp::Sub, symbol("end"), symbol("start"));
+        assert_eq!(expr.relocation(&symbols).unwrap(), None);
+        let expr = Expr::binary(BinaryOp::Sub, symbol("print"), Expr::Number(1));
+        assert_eq!(
+            expr.relocation(&symbols).unwrap(),
+            Some(RelocationTarget::Symbol("print".to_string()))
+        );
+
+        let expr = Expr::binary(BinaryOp::Mul, symbol("start"), Expr::Number(2));
+        assert!(matches!(
+            expr.relocation(&symbols),
+            Err(TokenError::NotRelocatable(_))
+        ));
+        let expr = Expr::binary(BinaryOp::Sub, symbol("table"), symbol("start"));
+        assert!(expr.relocation(&symbols).is_err());
+        let expr = Expr::binary(BinaryOp::Sub, symbol("SIZE"), symbol("print"));
+        assert!(expr.relocation(&symbols).is_err());
+    }
+}
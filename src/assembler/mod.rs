@@ -1,2 +1,732 @@
+pub mod expression;
 pub mod parsing;
 pub mod token;
+
+mod diagnostic;
+mod error;
+mod preprocessor;
+mod symbols;
+
+pub use diagnostic::{Diagnostic, Diagnostics, Span};
+pub use error::AssemblerError;
+pub use symbols::SymbolTable;
+
+use crate::bytecode::{DebugInfo, Executable, LineEntry, Object, Relocation, SectionKind};
+use diagnostic::diagnose;
+use parsing::{instruction, label_decl, Instruction, ParsingError};
+use preprocessor::Expansion;
+use std::path::{Path, PathBuf};
+use token::TokenError;
+
+/// Label execution starts from, if declared.
+pub const ENTRY_LABEL: &str = "main";
+
+/// Two-pass assembler.
+///
+/// The first pass builds a symbol table from label declarations,
+/// the second one encodes instructions substituting label usages
+/// with the resolved byte offsets.
+///
+/// Instructions go to the code section, data declarations
+/// following a `.data` directive go to the read-only data section.
+/// Labels resolve to offsets within their own section.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    symbols: SymbolTable,
+    /// Directory `.include` paths of the source are relative to.
+    base_dir: PathBuf,
+    debug_info: DebugInfo,
+}
+
+/// Parsed instruction along with its location in the source.
+struct SourceInstruction {
+    instr: Instruction,
+    span: Span,
+}
+
+impl Assembler {
+    pub fn new() -> Assembler {
+        Assembler::default()
+    }
+
+    /// Resolves `.include` paths relative to the given directory
+    /// instead of the current one.
+    pub fn with_base_dir(mut self, dir: &Path) -> Assembler {
+        self.base_dir = dir.to_path_buf();
+        self
+    }
+
+    /// Assembles the given source into an executable.
+    ///
+    /// Execution starts at the `main` label if it is declared
+    /// and at the first instruction otherwise. All errors found
+    /// in the source are reported at once.
+    pub fn assemble(&mut self, source: &str) -> Result<Executable, Diagnostics> {
+        let object = self.build(source, false)?;
+        Ok(Executable {
+            entry_point: self.symbols.symbol_value(ENTRY_LABEL).unwrap_or(0),
+            code: object.code,
+            ro_data: object.ro_data,
+            symbols: object.symbols,
+            debug_info: object.debug_info,
+        })
+    }
+
+    /// Assembles the given source into an object to be linked
+    /// with others, see `linker::link`.
+    ///
+    /// Labels listed by `.export` are visible to other objects,
+    /// the ones listed by `.import` have to be exported by them.
+    pub fn assemble_object(&mut self, source: &str) -> Result<Object, Diagnostics> {
+        self.build(source, true)
+    }
+
+    /// Runs both passes, recording relocations if the object
+    /// is going to be linked.
+    fn build(&mut self, source: &str, relocatable: bool) -> Result<Object, Diagnostics> {
+        let mut diagnostics = Diagnostics::default();
+        let expansion = preprocessor::expand(source, &self.base_dir, &mut diagnostics);
+        let instructions = parse(&expansion, &mut diagnostics);
+        let parsed = diagnostics.is_empty();
+        self.symbols = SymbolTable::new();
+        self.extract_labels(&expansion, &instructions, &mut diagnostics);
+        let imports =
+            self.declare_imports(&expansion, &instructions, relocatable, &mut diagnostics);
+        self.define_constants(&expansion, &instructions, parsed, &mut diagnostics);
+        self.debug_info = DebugInfo {
+            files: expansion
+                .file_names()
+                .map(|name| name.unwrap_or_default().to_string())
+                .collect(),
+            lines: vec![],
+        };
+
+        let mut code = vec![];
+        let mut ro_data = vec![];
+        let mut relocations = vec![];
+        let mut section = SectionKind::Code;
+        for SourceInstruction { instr, span } in &instructions {
+            let encoded = section_of(instr, section).and_then(|next| {
+                section = next;
+                let bytes = instr.to_bytes(&self.symbols)?;
+                match relocatable {
+                    true => Ok((bytes, instr.relocations(&self.symbols)?)),
+                    false => Ok((bytes, vec![])),
+                }
+            });
+            match encoded {
+                Ok((mut bytes, instr_relocations)) => {
+                    let output = match section {
+                        SectionKind::ReadOnlyData => &mut ro_data,
+                        _ => &mut code,
+                    };
+                    if section == SectionKind::Code && !bytes.is_empty() {
+                        let (file, line, column) = expansion.source_location(*span);
+                        self.debug_info.lines.push(LineEntry {
+                            offset: output.len() as u32,
+                            file: file as u32,
+                            line: line as u32,
+                            column: column as u32,
+                        });
+                    }
+                    for (offset, kind, target) in instr_relocations {
+                        relocations.push(Relocation {
+                            section,
+                            offset: (output.len() + offset) as u32,
+                            kind,
+                            target,
+                        });
+                    }
+                    output.append(&mut bytes);
+                }
+                Err(e) => report(&mut diagnostics, parsed, e, &expansion, *span),
+            }
+        }
+        let exports = self.exports(&expansion, &instructions, &mut diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.sort();
+            return Err(diagnostics);
+        }
+
+        Ok(Object {
+            code,
+            ro_data,
+            symbols: self.symbols.to_vec(),
+            exports,
+            imports,
+            relocations,
+            debug_info: self.debug_info.clone(),
+        })
+    }
+
+    /// Returns source locations of the code assembled last.
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    /// Returns a symbol table built during the last assembly.
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    /// First pass: records an offset of every declared label.
+    fn extract_labels(
+        &mut self,
+        expansion: &Expansion,
+        instructions: &[SourceInstruction],
+        diagnostics: &mut Diagnostics,
+    ) {
+        let mut code_offset = 0;
+        let mut data_offset = 0;
+        let mut section = SectionKind::Code;
+        for SourceInstruction { instr, span } in instructions {
+            // Misplaced and invalid instructions are reported by the second pass
+            section = section_of(instr, section).unwrap_or(section);
+            let offset = match section {
+                SectionKind::ReadOnlyData => &mut data_offset,
+                _ => &mut code_offset,
+            };
+            if let Some(name) = instr.label_name() {
+                if !self.symbols.add_symbol(name, section, *offset as u32) {
+                    let error = AssemblerError::DuplicateLabel(name.to_string());
+                    diagnostics.push(expansion.diagnostic(error, *span));
+                }
+            }
+            *offset += instr.encoded_len().unwrap_or(0);
+        }
+    }
+
+    /// Records the labels listed by `.import`, which are only
+    /// allowed in objects.
+    fn declare_imports(
+        &mut self,
+        expansion: &Expansion,
+        instructions: &[SourceInstruction],
+        relocatable: bool,
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<String> {
+        let mut imports = vec![];
+        for SourceInstruction { instr, span } in instructions {
+            if instr.directive_name() != Some("import") {
+                continue;
+            }
+            let names = match instr.linkage() {
+                Some(Ok(names)) => names,
+                Some(Err(e)) => {
+                    diagnostics.push(expansion.diagnostic(e.into(), *span));
+                    continue;
+                }
+                None => continue,
+            };
+            for name in names {
+                let error = if !relocatable {
+                    AssemblerError::UnresolvedImport(name.to_string())
+                } else if self.symbols.add_import(name) {
+                    imports.push(name.to_string());
+                    continue;
+                } else {
+                    AssemblerError::DuplicateLabel(name.to_string())
+                };
+                diagnostics.push(expansion.diagnostic(error, *span));
+            }
+        }
+        imports
+    }
+
+    /// Collects the labels listed by `.export`, which have to be declared.
+    fn exports(
+        &self,
+        expansion: &Expansion,
+        instructions: &[SourceInstruction],
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<String> {
+        let mut exports = vec![];
+        for SourceInstruction { instr, span } in instructions {
+            if instr.directive_name() != Some("export") {
+                continue;
+            }
+            let names = match instr.linkage() {
+                Some(Ok(names)) => names,
+                Some(Err(e)) => {
+                    diagnostics.push(expansion.diagnostic(e.into(), *span));
+                    continue;
+                }
+                None => continue,
+            };
+            for name in names {
+                if self.symbols.section(name).is_none() {
+                    let error = ParsingError::from(TokenError::UndefinedLabel(name.to_string()));
+                    diagnostics.push(expansion.diagnostic(error.into(), *span));
+                } else if !exports.iter().any(|export| export == name) {
+                    exports.push(name.to_string());
+                }
+            }
+        }
+        exports
+    }
+
+    /// Evaluates `.equ` constants in the source order, so a constant
+    /// may refer to the labels and to the constants defined above it.
+    fn define_constants(
+        &mut self,
+        expansion: &Expansion,
+        instructions: &[SourceInstruction],
+        parsed: bool,
+        diagnostics: &mut Diagnostics,
+    ) {
+        for SourceInstruction { instr, span } in instructions {
+            let error = match instr.constant(&self.symbols) {
+                Some(Ok((name, value))) if self.symbols.add_constant(name, value) => continue,
+                Some(Ok((name, _))) => AssemblerError::DuplicateLabel(name.to_string()),
+                Some(Err(e)) => e.into(),
+                None => continue,
+            };
+            report(diagnostics, parsed, error, expansion, *span);
+        }
+    }
+}
+
+/// Records an error, unless it is an undefined label while some
+/// lines failed to parse: labels declared on them are missing.
+fn report(
+    diagnostics: &mut Diagnostics,
+    parsed: bool,
+    error: AssemblerError,
+    expansion: &Expansion,
+    span: Span,
+) {
+    let undefined = matches!(
+        error,
+        AssemblerError::Parsing(ParsingError::InvalidOperand(TokenError::UndefinedLabel(_)))
+    );
+    if parsed || !undefined {
+        diagnostics.push(expansion.diagnostic(error, span));
+    }
+}
+
+/// Parses the expanded source instruction by instruction, recording
+/// a diagnostic for every line which fails to parse and skipping it.
+///
+/// Spans of the instructions refer to the expanded text.
+fn parse(expansion: &Expansion, diagnostics: &mut Diagnostics) -> Vec<SourceInstruction> {
+    let source = expansion.text.as_str();
+    let mut instructions = vec![];
+    let mut rest = skip_blank(source);
+    while !rest.is_empty() {
+        let offset = source.len() - rest.len();
+        let line = rest.lines().next().unwrap_or_default();
+        match instruction(rest) {
+            Ok((next, instr)) => {
+                let len = line.split(';').next().unwrap_or_default().trim_end().len();
+                let span = Span::new(source, offset, len);
+                instructions.push(SourceInstruction { instr, span });
+                rest = next;
+            }
+            Err(_) => {
+                let mut end = line.len();
+                // Label on its own line belongs to the instruction on the next one
+                if matches!(label_decl(line.trim_end()), Ok(("", _))) {
+                    let next = rest[end..].trim_start();
+                    end = rest.len() - next.len() + next.lines().next().unwrap_or_default().len();
+                }
+                let (error, start, len) = diagnose(&rest[..end]);
+                let span = Span::new(source, offset + start, len);
+                diagnostics.push(expansion.diagnostic(error, span));
+                rest = &rest[end..];
+            }
+        }
+        rest = skip_blank(rest);
+    }
+    instructions
+}
+
+/// Skips whitespace and comment lines.
+fn skip_blank(mut rest: &str) -> &str {
+    loop {
+        rest = rest.trim_start();
+        if !rest.starts_with(';') {
+            return rest;
+        }
+        rest = rest.find('\n').map_or("", |i| &rest[i..]);
+    }
+}
+
+/// Returns a section the instruction belongs to, given the current one,
+/// and checks that it is allowed there.
+fn section_of(instr: &Instruction, current: SectionKind) -> Result<SectionKind, AssemblerError> {
+    let section = match instr.directive_name() {
+        Some("code") => SectionKind::Code,
+        Some("data") => SectionKind::ReadOnlyData,
+        _ => current,
+    };
+    // Raw bytes are allowed in code too, e.g. for what
+    // the disassembler can't decode
+    let is_data = matches!(instr.directive_name(), Some("asciiz") | Some("integer"));
+    let is_code = instr.directive_name().is_none();
+    match section {
+        SectionKind::Code if is_data => Err(AssemblerError::WrongSection(instr.to_string())),
+        SectionKind::ReadOnlyData if is_code => {
+            Err(AssemblerError::WrongSection(instr.to_string()))
+        }
+        _ => Ok(section),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bytecode::Symbol;
+    use crate::instruction::{Opcode, OperandKind};
+    use crate::vm::{ExitReason, VM};
+    use token::Token;
+
+    #[test]
+    fn test_assemble_labels() {
+        let mut asm = Assembler::new();
+        let code = "
+            load $0 #3
+        loop: dec $0
+            load $1 #0
+            jneq @loop
+            hlt
+        ";
+        let bytes = asm.assemble(code).unwrap().code;
+        assert_eq!(asm.symbols().symbol_value("loop"), Some(4));
+        assert_eq!(&bytes[10..13], &[Opcode::JNEQI.into(), 0, 4]);
+    }
+
+    #[test]
+    fn test_assemble_forward_label() {
+        let mut asm = Assembler::new();
+        let executable = asm.assemble("jmp @done\nload $0 #1\ndone: hlt\n").unwrap();
+        assert_eq!(executable.code[..3], [Opcode::JMPI.into(), 0, 7]);
+
+        let mut vm = VM::new();
+        vm.load(&executable.to_bytes()).unwrap();
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 0);
+    }
+
+    #[test]
+    fn test_assemble_entry_point() {
+        let mut asm = Assembler::new();
+        let executable = asm.assemble("load $0 #1\nmain: hlt\n").unwrap();
+        assert_eq!(executable.entry_point, 4);
+        assert_eq!(
+            executable.symbols,
+            vec![Symbol {
+                name: "main".to_string(),
+                section: SectionKind::Code,
+                offset: 4
+            }]
+        );
+    }
+
+    #[test]
+    fn test_assemble_loop() {
+        let mut asm = Assembler::new();
+        let code = "
+            load $0 #0
+            load $1 #10
+        loop: inc $0
+            lt $0 $1
+            jeq @loop
+            hlt
+        ";
+        let mut vm = VM::new();
+        vm.load_executable(asm.assemble(code).unwrap());
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 10);
+    }
+
+    #[test]
+    fn test_assemble_floats() {
+        let mut asm = Assembler::new();
+        let code = "
+            loadf64 $0 #1.5
+            load $0 #2
+            itof $0 $1
+            mulf64 $0 $1 $2
+            ftoi $2 $3
+            hlt
+        ";
+        let mut vm = VM::new();
+        vm.load_executable(asm.assemble(code).unwrap());
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.float_registers[2], 3.0);
+        assert_eq!(vm.registers[3], 3);
+    }
+
+    #[test]
+    fn test_assemble_call() {
+        let mut asm = Assembler::new();
+        let code = "
+        main: load $0 #5
+            call @double
+            hlt
+        double: add $0 $0 $0
+            ret
+        ";
+        let executable = asm.assemble(code).unwrap();
+        assert_eq!(executable.code[4..7], [Opcode::CALLI.into(), 0, 8]);
+
+        let mut vm = VM::new();
+        vm.load_executable(executable);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 10);
+        assert_eq!(vm.sp(), 0);
+    }
+
+    #[test]
+    fn test_assemble_data() {
+        let mut asm = Assembler::new();
+        let code = "
+        .data
+        hello: .asciiz \"Hello\"
+        table: .integer #1 #2
+        bytes: .bytes #7 #8
+        .code
+        main: load $0 @table
+            hlt
+        ";
+        let executable = asm.assemble(code).unwrap();
+        assert_eq!(asm.symbols().symbol_value("hello"), Some(0));
+        assert_eq!(asm.symbols().symbol_value("table"), Some(6));
+        assert_eq!(asm.symbols().symbol_value("bytes"), Some(14));
+        assert_eq!(asm.symbols().symbol_value("main"), Some(0));
+        assert_eq!(
+            executable.code,
+            vec![Opcode::LOAD.into(), 0, 0, 6, Opcode::HLT.into()]
+        );
+        assert_eq!(
+            executable.ro_data,
+            b"Hello\0\0\0\0\x01\0\0\0\x02\x07\x08".to_vec()
+        );
+    }
+
+    fn assemble_errors(source: &str) -> Vec<(AssemblerError, Span)> {
+        let diagnostics = Assembler::new().assemble(source).unwrap_err();
+        diagnostics
+            .iter()
+            .map(|d| (d.error.clone(), d.span))
+            .collect()
+    }
+
+    #[test]
+    fn test_assemble_literals() {
+        let mut asm = Assembler::new();
+        let code = "
+        .data
+        chars: .bytes #'h' #'\\n' #0x7f #0b1
+        .code
+            loadw $0 #0xFFFF_0000
+            load $1 #'A'
+            hlt
+        ";
+        let executable = asm.assemble(code).unwrap();
+        assert_eq!(executable.ro_data, vec![b'h', b'\n', 0x7f, 1]);
+        let mut vm = VM::new();
+        vm.load_executable(executable);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0] as u32, 0xFFFF_0000);
+        assert_eq!(vm.registers[1], 65);
+
+        let errors = assemble_errors("load $0 #0xFFFF\nload $0 #0x1_0000_0000\n");
+        assert!(matches!(
+            errors[0].0,
+            AssemblerError::Parsing(ParsingError::InvalidOperand(TokenError::OutOfRange(
+                0xFFFF,
+                _
+            )))
+        ));
+        assert!(matches!(
+            errors[1].0,
+            AssemblerError::Parsing(ParsingError::NumberOutOfRange(_))
+        ));
+    }
+
+    #[test]
+    fn test_assemble_constants() {
+        let mut asm = Assembler::new();
+        let code = "
+        .equ SIZE #4
+        .equ DOUBLE #(SIZE * 2)
+        .data
+        table: .integer #(SIZE + 1) #-1
+        end: .bytes #(end - table)
+        .code
+        main: load $0 #DOUBLE
+            load $1 @table+4
+            load $2 #(-SIZE << 2 | 1)
+            loadw $3 #('a' ^ 0x20)
+            hlt
+        ";
+        let executable = asm.assemble(code).unwrap();
+        assert_eq!(asm.symbols().value("DOUBLE"), Some(8));
+        assert_eq!(executable.ro_data, vec![0, 0, 0, 5, 255, 255, 255, 255, 8]);
+        let mut vm = VM::new();
+        vm.load_executable(executable);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[..4], [8, 4, -15, 65]);
+
+        let errors = assemble_errors(
+            ".equ A #B\n.equ B #(1 / 0)\n.equ B #2\n.equ B #3\nmain: hlt\n.equ main #1\n.equ C\n",
+        );
+        assert_eq!(errors.len(), 5);
+        assert!(matches!(
+            errors[0].0,
+            AssemblerError::Parsing(ParsingError::InvalidOperand(TokenError::UndefinedLabel(_)))
+        ));
+        assert!(matches!(
+            errors[1].0,
+            AssemblerError::Parsing(ParsingError::InvalidOperand(TokenError::InvalidExpression(
+                _
+            )))
+        ));
+        assert!(matches!(errors[2].0, AssemblerError::DuplicateLabel(_)));
+        assert!(matches!(errors[3].0, AssemblerError::DuplicateLabel(_)));
+        assert!(matches!(
+            errors[4].0,
+            AssemblerError::Parsing(ParsingError::InvalidOperands(_))
+        ));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let mut assembler = Assembler::new();
+        assembler
+            .assemble(
+                ".macro twice r\ninc \\r\ninc \\r\n.endm\nmain: load $0 #1\n  twice $0\n\nhlt ; done\n",
+            )
+            .unwrap();
+        let debug_info = assembler.debug_info();
+        assert_eq!(debug_info.files, vec![""]);
+        let lines: Vec<_> = debug_info
+            .lines
+            .iter()
+            .map(|entry| (entry.offset, entry.file, entry.line, entry.column))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![(0, 0, 5, 1), (4, 0, 6, 3), (6, 0, 6, 3), (8, 0, 8, 1)]
+        );
+        assert_eq!(debug_info.location(4).map(|entry| entry.line), Some(6));
+        assert_eq!(debug_info.location(5), None);
+        assert_eq!(
+            debug_info.line_start(0, 7).map(|entry| entry.offset),
+            Some(8)
+        );
+        assert_eq!(debug_info.line_start(0, 9), None);
+    }
+
+    #[test]
+    fn test_assemble_macros() {
+        let mut asm = Assembler::new();
+        let code = "
+        .macro countdown reg from
+            load \\reg \\from
+            load $31 #0
+        again: dec \\reg
+            eq \\reg $31
+            jneq @again
+        .endm
+        main: countdown $0 #3
+            countdown $1 #(2 * 2)
+            hlt
+        ";
+        let mut vm = VM::new();
+        vm.load_executable(asm.assemble(code).unwrap());
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[..2], [0, 0]);
+
+        let code = "
+        .macro set reg value
+            load \\reg \\value
+        .endm
+            set $0 #1
+            set $40 #1
+        ";
+        let diagnostics = Assembler::new().assemble(code).unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.span.line, 3);
+        assert_eq!(diagnostic.notes.len(), 1);
+        assert_eq!(diagnostic.notes[0].span.line, 6);
+        assert!(diagnostic
+            .to_string()
+            .ends_with("note: in expansion of macro set\n --> <input>:6:13\n  |\n6 |             set $40 #1\n  |             ^^^^^^^^^^"));
+    }
+
+    #[test]
+    fn test_assemble_wrong_section() {
+        let errors = assemble_errors(".asciiz \"Hello\"\n.bytes #1\n.data\nhlt\n.float #1\n");
+        assert_eq!(errors.len(), 3);
+        assert!(matches!(errors[0].0, AssemblerError::WrongSection(_)));
+        assert!(matches!(errors[1].0, AssemblerError::WrongSection(_)));
+        assert!(matches!(errors[2].0, AssemblerError::Parsing(_)));
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        let errors = assemble_errors("a: hlt\na: hlt\n");
+        assert!(matches!(errors[0].0, AssemblerError::DuplicateLabel(_)));
+        let errors = assemble_errors("jmp @missing\n");
+        assert!(matches!(errors[0].0, AssemblerError::Parsing(_)));
+        let errors = assemble_errors("hlt\n$1 $2\n");
+        assert!(matches!(errors[0].0, AssemblerError::UnparsedInput(_)));
+        let errors = assemble_errors("load $0 #70000\n");
+        assert_eq!(
+            errors[0].0.to_string(),
+            "Invalid operand: Value 70000 does not fit into integer operand"
+        );
+        let error =
+            TokenError::UnexpectedOperand(Token::Register { reg_num: 1 }, OperandKind::Address);
+        assert_eq!(error.to_string(), "Expected label operand, found: $1");
+    }
+
+    #[test]
+    fn test_assemble_diagnostics() {
+        let source = "
+            load $0 #1
+            daol $1
+        loop: add $0 $40 $1
+            load $1 #70000
+            lt $0 $1 $2
+        end:
+            jmp @loop ; back
+            ";
+        let errors = assemble_errors(source);
+        let spans: Vec<_> = errors
+            .iter()
+            .map(|(_, span)| (span.line, span.column, span.len))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![(3, 13, 4), (4, 22, 3), (5, 13, 14), (6, 13, 11)]
+        );
+        assert!(matches!(
+            errors[0].0,
+            AssemblerError::Parsing(ParsingError::UnknownOpcode(_))
+        ));
+        assert!(matches!(
+            errors[1].0,
+            AssemblerError::Parsing(ParsingError::InvalidRegister(_))
+        ));
+        assert!(matches!(
+            errors[2].0,
+            AssemblerError::Parsing(ParsingError::InvalidOperand(TokenError::OutOfRange(
+                70000,
+                _
+            )))
+        ));
+        assert!(matches!(
+            errors[3].0,
+            AssemblerError::Parsing(ParsingError::WrongOperandCount { .. })
+        ));
+
+        let diagnostics = Assembler::new().assemble(source).unwrap_err();
+        let text = diagnostics.with_file("prog.iasm").to_string();
+        assert!(text.contains("--> prog.iasm:3:13"));
+        assert!(text.ends_with("4 error(s) found"));
+    }
+}
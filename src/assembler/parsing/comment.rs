@@ -1,6 +1,6 @@
 use nom::{
-    character::complete::{multispace0, space0},
-    do_parse, named, opt, tag, take_until,
+    character::complete::{multispace0, not_line_ending, space0},
+    complete, do_parse, named, tag,
 };
 
 use crate::assembler::token::Token;
@@ -9,8 +9,8 @@ named!(
     pub comment<&str, Token>,
     do_parse!(
         space0 >>
-        tag!(";") >>
-        take_until!("\n") >>
+        complete!(tag!(";")) >>
+        not_line_ending >>
         multispace0 >>
         (
             Token::Comment
@@ -26,5 +26,7 @@ mod tests {
     fn test_parse_comment() {
         let actual = comment("; blah blah\n   \t\n123");
         assert_eq!(Ok(("123", Token::Comment)), actual);
+        let actual = comment("; at the end of input");
+        assert_eq!(Ok(("", Token::Comment)), actual);
     }
 }
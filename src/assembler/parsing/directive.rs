@@ -1,14 +1,34 @@
-use nom::{character::complete::multispace0, do_parse, named, tag, take_until};
+use nom::{character::complete::alpha1, complete, do_parse, named, tag};
 
 use crate::assembler::token::Token;
 
 named!(
     pub directive<&str, Token>,
     do_parse!(
-        tag!(".") >>
+        complete!(tag!(".")) >>
         name: alpha1 >>
         (
             Token::Directive { name: name.to_string() }
         )
     )
 );
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_directive() {
+        let result = directive(".data");
+        assert_eq!(
+            result,
+            Ok((
+                "",
+                Token::Directive {
+                    name: "data".to_string()
+                }
+            ))
+        );
+        assert!(directive("data").is_err());
+    }
+}
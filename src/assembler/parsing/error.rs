@@ -1,4 +1,5 @@
-use crate::assembler::token::Token;
+use crate::assembler::token::{Token, TokenError};
+use crate::instruction::REGISTER_COUNT;
 use std::error::Error;
 use std::fmt::{self, Display};
 
@@ -6,6 +7,22 @@ use std::fmt::{self, Display};
 pub enum ParsingError {
     OpcodeExpected(Token),
     UnknownOpcode(String),
+    /// Register number is not below `REGISTER_COUNT`.
+    InvalidRegister(String),
+    NumberOutOfRange(String),
+    InvalidOperand(TokenError),
+    WrongOperandCount {
+        mnemonic: String,
+        expected: usize,
+        found: usize,
+    },
+    /// No opcode with the given mnemonic accepts these operands.
+    InvalidOperands(String),
+    UnknownDirective(String),
+    InvalidArgument {
+        directive: String,
+        argument: Token,
+    },
 }
 
 impl Display for ParsingError {
@@ -17,8 +34,51 @@ impl Display for ParsingError {
             ParsingError::UnknownOpcode(s) => {
                 write!(f, "Unknown opcode: {}", s)
             }
+            ParsingError::InvalidRegister(s) => {
+                write!(
+                    f,
+                    "Invalid register: {} (expected $0 to ${})",
+                    s,
+                    REGISTER_COUNT - 1
+                )
+            }
+            ParsingError::NumberOutOfRange(s) => {
+                write!(f, "Number out of range: {}", s)
+            }
+            ParsingError::InvalidOperand(e) => {
+                write!(f, "Invalid operand: {}", e)
+            }
+            ParsingError::InvalidOperands(instr) => {
+                write!(f, "Invalid operands: {}", instr)
+            }
+            ParsingError::WrongOperandCount {
+                mnemonic,
+                expected,
+                found,
+            } => {
+                write!(
+                    f,
+                    "{} expects {} operand(s), found {}",
+                    mnemonic, expected, found
+                )
+            }
+            ParsingError::UnknownDirective(name) => {
+                write!(f, "Unknown directive: .{}", name)
+            }
+            ParsingError::InvalidArgument {
+                directive,
+                argument,
+            } => {
+                write!(f, "Invalid argument of .{}: {}", directive, argument)
+            }
         }
     }
 }
 
 impl Error for ParsingError {}
+
+impl From<TokenError> for ParsingError {
+    fn from(e: TokenError) -> Self {
+        ParsingError::InvalidOperand(e)
+    }
+}
@@ -0,0 +1,166 @@
+use crate::assembler::expression::{BinaryOp, Expr};
+use crate::assembler::token::Token;
+use nom::character::complete::space0;
+use nom::{alt, char, complete, delimited, map, named, pair, peek, preceded, IResult};
+
+use super::{
+    label::identifier,
+    operand::{char_literal, integer_literal},
+};
+
+/// Parses a left-associative chain of operands separated by any of the operators.
+fn chain<'a>(
+    input: &'a str,
+    operators: &[&str],
+    operand: fn(&'a str) -> IResult<&'a str, Expr>,
+) -> IResult<&'a str, Expr> {
+    let (mut rest, mut expr) = operand(input)?;
+    loop {
+        let (after_op, op) = match space0::<_, ()>(rest) {
+            Ok((after_space, _)) => match operators.iter().find(|op| after_space.starts_with(**op))
+            {
+                Some(op) => (&after_space[op.len()..], *op),
+                None => return Ok((rest, expr)),
+            },
+            Err(_) => return Ok((rest, expr)),
+        };
+        let (after_space, _) = space0(after_op)?;
+        match operand(after_space) {
+            Ok((next, rhs)) => {
+                expr = Expr::binary(BinaryOp::from(op), expr, rhs);
+                rest = next;
+            }
+            Err(nom::Err::Error(_)) => return Ok((rest, expr)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+named!(
+    parenthesized<&str, Expr>,
+    delimited!(
+        pair!(complete!(char!('(')), space0),
+        expression,
+        pair!(space0, complete!(char!(')')))
+    )
+);
+
+named!(
+    atom<&str, Expr>,
+    alt!(
+        map!(char_literal, |value| Expr::Number(value as i64)) |
+        map!(integer_literal, |value| Expr::Number(value as i64)) |
+        map!(preceded!(complete!(char!('@')), identifier), |name| Expr::Symbol(name.to_string())) |
+        map!(identifier, |name| Expr::Symbol(name.to_string())) |
+        parenthesized
+    )
+);
+
+named!(
+    unary<&str, Expr>,
+    alt!(
+        atom |
+        map!(preceded!(complete!(char!('-')), unary), |expr| Expr::Neg(Box::new(expr))) |
+        map!(preceded!(complete!(char!('~')), unary), |expr| Expr::Not(Box::new(expr)))
+    )
+);
+
+fn product(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["*", "/", "%"], unary)
+}
+
+fn sum(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["+", "-"], product)
+}
+
+fn shift(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["<<", ">>"], sum)
+}
+
+fn bit_and(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["&"], shift)
+}
+
+fn bit_xor(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["^"], bit_and)
+}
+
+/// Parses a constant expression, operators have the C precedence.
+pub fn expression(input: &str) -> IResult<&str, Expr> {
+    chain(input, &["|"], bit_xor)
+}
+
+/// Turns a label usage without arithmetic back into a plain label token.
+fn label_or_expression(expr: Expr) -> Token {
+    match expr {
+        Expr::Symbol(name) => Token::LabelUsage { name },
+        expr => Token::Expression { expr },
+    }
+}
+
+named!(
+    pub expression_operand<&str, Token>,
+    alt!(
+        map!(
+            preceded!(
+                complete!(char!('#')),
+                alt!(parenthesized | map!(identifier, |name| Expr::Symbol(name.to_string())))
+            ),
+            |expr| Token::Expression { expr }
+        ) |
+        map!(preceded!(peek!(complete!(char!('@'))), expression), label_or_expression)
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> String {
+        let (rest, expr) = expression(input).unwrap();
+        assert_eq!(rest, "", "{}", input);
+        expr.to_string()
+    }
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(parse("1+2*3"), "(1 + (2 * 3))");
+        assert_eq!(parse("(1 + 2) * 3"), "((1 + 2) * 3)");
+        assert_eq!(parse("1 - 2 - 3"), "((1 - 2) - 3)");
+        assert_eq!(parse("1 << 2 + 1"), "(1 << (2 + 1))");
+        assert_eq!(parse("0xF0 | 0x0F & 3"), "(240 | (15 & 3))");
+        assert_eq!(parse("-SIZE * ~'A'"), "(-SIZE * ~65)");
+        assert_eq!(parse("@table+4"), "(table + 4)");
+        assert_eq!(expression("1 + ; comment").unwrap().0, " + ; comment");
+    }
+
+    #[test]
+    fn test_parse_expression_operand() {
+        let (rest, token) = expression_operand("#(SIZE * 2) $1").unwrap();
+        assert_eq!(rest, " $1");
+        assert_eq!(token.to_string(), "(SIZE * 2)");
+
+        let (_, token) = expression_operand("#SIZE").unwrap();
+        assert_eq!(
+            token,
+            Token::Expression {
+                expr: Expr::Symbol("SIZE".to_string())
+            }
+        );
+
+        let (rest, token) = expression_operand("@table+4 ; comment").unwrap();
+        assert_eq!(rest, " ; comment");
+        assert_eq!(token.to_string(), "(table + 4)");
+
+        let (_, token) = expression_operand("@loop").unwrap();
+        assert_eq!(
+            token,
+            Token::LabelUsage {
+                name: "loop".to_string()
+            }
+        );
+
+        assert!(expression_operand("#(1 + 2").is_err());
+        assert!(expression_operand("#5").is_err());
+    }
+}
@@ -1,93 +1,353 @@
 use crate::assembler::{
-    parsing::{comment::comment, label::label_decl, opcode, operand::operand, ParsingError},
-    token::Token,
+    parsing::{
+        comment::comment,
+        directive,
+        label::{label_decl, symbol},
+        opcode,
+        operand::operand,
+        string, ParsingError,
+    },
+    symbols::SymbolTable,
+    token::{Token, TokenError},
 };
+use crate::bytecode::RelocationTarget;
+use crate::instruction::{Opcode, OpcodeInfo, OperandKind};
+
+use std::fmt::{self, Display};
 
 use nom::{
-    character::complete::{multispace0, space1},
-    do_parse, named, opt, preceded,
+    alt,
+    character::complete::{line_ending, multispace0, space0, space1},
+    do_parse, eof, many0, named, opt, preceded, terminated, value,
 };
 
+/// Names of the directives known to the assembler.
+pub const DIRECTIVES: [&str; 8] = [
+    "data", "code", "asciiz", "integer", "bytes", "equ", "import", "export",
+];
+
+/// Single line of assembly: either an opcode with its operands
+/// or a directive with its arguments.
 #[derive(Debug, Clone, PartialEq)]
 pub struct Instruction {
-    opcode: Token,
+    opcode: Option<Token>,
     label: Option<Token>,
     directive: Option<Token>,
     operand1: Option<Token>,
     operand2: Option<Token>,
     operand3: Option<Token>,
+    arguments: Vec<Token>,
 }
 
 impl Instruction {
-    pub fn to_bytes(&self) -> Result<Vec<u8>, ParsingError> {
-        let mut bytes = vec![];
-        let opcode = self
-            .opcode_bytes()
-            .ok_or_else(|| ParsingError::OpcodeExpected(self.opcode.clone()))?;
-        let mut operands = self.operand_bytes();
-        bytes.push(opcode);
-        bytes.append(&mut operands);
+    /// Encodes an opcode instruction into code bytes or
+    /// a data directive into read-only data bytes.
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, ParsingError> {
+        if let Some(name) = self.directive_name() {
+            return self.directive_bytes(name, symbols);
+        }
+        let info = self.resolve_opcode()?;
+        let mut bytes = vec![info.code];
+        for (op, kind) in self.operands().zip(info.operands) {
+            bytes.append(&mut op.operand_bytes(*kind, symbols)?);
+        }
         Ok(bytes)
     }
 
-    fn opcode_bytes(&self) -> Option<u8> {
-        if let Token::Op { code } = &self.opcode {
-            Some(code.clone().into())
-        } else {
-            None
+    /// Returns a number of bytes this instruction occupies
+    /// in the program bytecode or in the read-only data.
+    pub fn encoded_len(&self) -> Result<usize, ParsingError> {
+        let len = match self.directive_name() {
+            Some("asciiz") => self
+                .arguments
+                .iter()
+                .map(|arg| match arg {
+                    Token::IrString { value } => value.len() + 1,
+                    _ => 0,
+                })
+                .sum(),
+            Some("integer") => 4 * self.arguments.len(),
+            Some("bytes") => self.arguments.len(),
+            Some(_) => 0,
+            None => self.resolve_opcode()?.instruction_len(),
+        };
+        Ok(len)
+    }
+
+    /// Picks an opcode with the instruction mnemonic
+    /// which accepts its operands.
+    pub fn resolve_opcode(&self) -> Result<&'static OpcodeInfo, ParsingError> {
+        let code = match &self.opcode {
+            Some(Token::Op { code }) => code,
+            _ => return Err(ParsingError::OpcodeExpected(self.opcode_token())),
+        };
+        let operands: Vec<_> = self.operands().collect();
+        let expected = Opcode::overloads(code.mnemonic())
+            .next()
+            .map_or(0, |info| info.operands.len());
+        if expected != operands.len() {
+            return Err(ParsingError::WrongOperandCount {
+                mnemonic: code.mnemonic().to_string(),
+                expected,
+                found: operands.len(),
+            });
         }
+        Opcode::overloads(code.mnemonic())
+            .find(|info| {
+                operands
+                    .iter()
+                    .zip(info.operands)
+                    .all(|(op, kind)| op.matches(*kind))
+            })
+            .ok_or_else(|| ParsingError::InvalidOperands(self.to_string()))
+    }
+
+    /// Returns the operands which have to be adjusted once the object
+    /// is linked: their offsets within the encoded instruction,
+    /// their kinds and the addresses they depend on.
+    pub fn relocations(
+        &self,
+        symbols: &SymbolTable,
+    ) -> Result<Vec<(usize, OperandKind, RelocationTarget)>, ParsingError> {
+        let mut relocations = vec![];
+        let mut add = |offset, token: &Token, kind: OperandKind| match token.relocation(symbols)? {
+            Some(_) if kind.range().is_none() => Err(TokenError::NotRelocatable(token.to_string())),
+            Some(target) => {
+                relocations.push((offset, kind, target));
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        match self.directive_name() {
+            Some("integer") => {
+                for (i, arg) in self.arguments.iter().enumerate() {
+                    add(4 * i, arg, OperandKind::Word)?;
+                }
+            }
+            Some("equ") => {
+                // Constants don't keep track of the addresses they depend on
+                if let Some(value) = self.arguments.get(1) {
+                    if value.relocation(symbols)?.is_some() {
+                        return Err(TokenError::NotRelocatable(value.to_string()).into());
+                    }
+                }
+            }
+            Some("bytes") => {
+                // Bytes are too narrow for an address
+                if let Some(arg) = self
+                    .arguments
+                    .iter()
+                    .find(|arg| matches!(arg.relocation(symbols), Ok(Some(_))))
+                {
+                    return Err(TokenError::NotRelocatable(arg.to_string()).into());
+                }
+            }
+            Some(_) => {}
+            None => {
+                let mut offset = 1;
+                for (op, kind) in self.operands().zip(self.resolve_opcode()?.operands) {
+                    add(offset, op, *kind)?;
+                    offset += kind.size();
+                }
+            }
+        }
+        Ok(relocations)
+    }
+
+    /// Returns the names listed by a `.import` or `.export` directive.
+    pub fn linkage(&self) -> Option<Result<Vec<&str>, ParsingError>> {
+        let directive = self
+            .directive_name()
+            .filter(|name| *name == "import" || *name == "export")?;
+        let names = self
+            .arguments
+            .iter()
+            .map(|arg| match arg {
+                Token::Symbol { name } => Ok(name.as_str()),
+                _ => Err(ParsingError::InvalidArgument {
+                    directive: directive.to_string(),
+                    argument: arg.clone(),
+                }),
+            })
+            .collect();
+        Some(names)
+    }
+
+    /// Returns a name of the label declared by this instruction.
+    pub fn label_name(&self) -> Option<&str> {
+        match &self.label {
+            Some(Token::LabelDecl { name }) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Evaluates a constant defined by `.equ NAME value`,
+    /// returning its name along with the value.
+    pub fn constant(&self, symbols: &SymbolTable) -> Option<Result<(&str, i64), ParsingError>> {
+        if self.directive_name() != Some("equ") {
+            return None;
+        }
+        let value = match self.arguments.as_slice() {
+            [Token::Symbol { name }, value] => value.evaluate(symbols).map(|value| (name, value)),
+            _ => None,
+        };
+        let result = match value {
+            Some((name, Ok(value))) => Ok((name.as_str(), value)),
+            Some((_, Err(e))) => Err(e.into()),
+            None => Err(ParsingError::InvalidOperands(self.to_string())),
+        };
+        Some(result)
+    }
+
+    /// Returns a name of the directive, if this instruction is one.
+    pub fn directive_name(&self) -> Option<&str> {
+        match &self.directive {
+            Some(Token::Directive { name }) => Some(name),
+            _ => None,
+        }
+    }
+
+    fn opcode_token(&self) -> Token {
+        self.opcode.clone().unwrap_or(Token::Comment)
+    }
+
+    fn operands(&self) -> impl Iterator<Item = &Token> {
+        vec![&self.operand1, &self.operand2, &self.operand3]
+            .into_iter()
+            .flatten()
     }
 
-    fn operand_bytes(&self) -> Vec<u8> {
+    fn directive_bytes(&self, name: &str, symbols: &SymbolTable) -> Result<Vec<u8>, ParsingError> {
+        if !DIRECTIVES.contains(&name) {
+            return Err(ParsingError::UnknownDirective(name.to_string()));
+        }
         let mut bytes = vec![];
-        let operands = vec![&self.operand1, &self.operand2, &self.operand3];
-        for op in operands.into_iter().flatten() {
-            bytes.append(&mut op.operand_bytes().unwrap())
+        if matches!(name, "equ" | "import" | "export") {
+            // These are handled by the first pass and take no space
+            return Ok(bytes);
+        }
+        for arg in &self.arguments {
+            let value = arg.evaluate(symbols).transpose()?;
+            match (name, arg, value) {
+                ("asciiz", Token::IrString { value }, _) => {
+                    bytes.extend_from_slice(value.as_bytes());
+                    bytes.push(0);
+                }
+                ("integer", _, Some(value)) if value as i32 as i64 == value => {
+                    bytes.extend_from_slice(&(value as i32).to_be_bytes());
+                }
+                ("bytes", _, Some(value)) if (0..=255).contains(&value) => {
+                    bytes.push(value as u8);
+                }
+                _ => {
+                    return Err(ParsingError::InvalidArgument {
+                        directive: name.to_string(),
+                        argument: arg.clone(),
+                    });
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+impl Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(name) = self.label_name() {
+            write!(f, "{}: ", name)?;
+        }
+        match (&self.opcode, &self.directive) {
+            (Some(opcode), _) => write!(f, "{}", opcode)?,
+            (None, Some(directive)) => write!(f, "{}", directive)?,
+            (None, None) => {}
         }
-        bytes
+        for token in self.operands().chain(self.arguments.iter()) {
+            write!(f, " {}", token)?;
+        }
+        Ok(())
     }
 }
 
 named!(
-    pub instruction<&str, Instruction>,
+    line_end<&str, ()>,
+    alt!(
+        value!((), comment) |
+        value!((), preceded!(space0, alt!(line_ending | eof!())))
+    )
+);
+
+named!(
+    opcode_instruction<&str, Instruction>,
     do_parse!(
     multispace0 >>
-    opt!(comment) >>
-    label: opt!(label_decl) >>
+    many0!(comment) >>
+    label: opt!(terminated!(label_decl, multispace0)) >>
     opcode: opcode >>
     operand1: opt!(preceded!(space1, operand)) >>
     operand2: opt!(preceded!(space1, operand)) >>
     operand3: opt!(preceded!(space1, operand)) >>
-    opt!(comment) >>
+    line_end >>
     multispace0 >>
     (
         Instruction {
-            opcode,
+            opcode: Some(opcode),
             label,
             directive: None,
             operand1,
             operand2,
-            operand3
+            operand3,
+            arguments: vec![]
         }
     )
     )
 );
 
+named!(
+    directive_instruction<&str, Instruction>,
+    do_parse!(
+    multispace0 >>
+    many0!(comment) >>
+    label: opt!(terminated!(label_decl, multispace0)) >>
+    directive: directive >>
+    arguments: many0!(preceded!(space1, alt!(operand | string | symbol))) >>
+    line_end >>
+    multispace0 >>
+    (
+        Instruction {
+            opcode: None,
+            label,
+            directive: Some(directive),
+            operand1: None,
+            operand2: None,
+            operand3: None,
+            arguments
+        }
+    )
+    )
+);
+
+named!(
+    pub instruction<&str, Instruction>,
+    alt!(directive_instruction | opcode_instruction)
+);
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::instruction::Opcode;
+    use crate::assembler::token::TokenError;
+    use crate::bytecode::SectionKind;
 
     #[test]
     fn test_parse_instruction_nullary() {
         let actual = instruction("hlt\n");
         let expected = Instruction {
-            opcode: Token::Op { code: Opcode::HLT },
+            opcode: Some(Token::Op { code: Opcode::HLT }),
             label: None,
             directive: None,
             operand1: None,
             operand2: None,
             operand3: None,
+            arguments: vec![],
         };
         assert_eq!(Ok(("", expected)), actual);
     }
@@ -96,12 +356,13 @@ mod tests {
     fn test_parse_instruction_binary() {
         let actual = instruction("load $0 #100  \n");
         let expected = Instruction {
-            opcode: Token::Op { code: Opcode::LOAD },
+            opcode: Some(Token::Op { code: Opcode::LOAD }),
             label: None,
             directive: None,
             operand1: Some(Token::Register { reg_num: 0 }),
             operand2: Some(Token::Number { value: 100 }),
             operand3: None,
+            arguments: vec![],
         };
         assert_eq!(Ok(("", expected)), actual);
     }
@@ -110,12 +371,13 @@ mod tests {
     fn test_parse_instruction_ternary() {
         let actual = instruction("add $0 $1 $2\n\n");
         let expected = Instruction {
-            opcode: Token::Op { code: Opcode::ADD },
+            opcode: Some(Token::Op { code: Opcode::ADD }),
             label: None,
             directive: None,
             operand1: Some(Token::Register { reg_num: 0 }),
             operand2: Some(Token::Register { reg_num: 1 }),
             operand3: Some(Token::Register { reg_num: 2 }),
+            arguments: vec![],
         };
         assert_eq!(Ok(("", expected)), actual);
     }
@@ -124,12 +386,13 @@ mod tests {
     fn test_parse_with_comment_preceded() {
         let actual = instruction("; whatever comment   \n  mul #100 $2 $3\n");
         let expected = Instruction {
-            opcode: Token::Op { code: Opcode::MUL },
+            opcode: Some(Token::Op { code: Opcode::MUL }),
             label: None,
             directive: None,
             operand1: Some(Token::Number { value: 100 }),
             operand2: Some(Token::Register { reg_num: 2 }),
             operand3: Some(Token::Register { reg_num: 3 }),
+            arguments: vec![],
         };
         assert_eq!(Ok(("", expected)), actual);
     }
@@ -138,13 +401,169 @@ mod tests {
     fn test_parse_with_comment_terminated() {
         let actual = instruction("jmpf $2    ; comment text\n");
         let expected = Instruction {
-            opcode: Token::Op { code: Opcode::JMPF },
+            opcode: Some(Token::Op { code: Opcode::JMPF }),
             label: None,
             directive: None,
             operand1: Some(Token::Register { reg_num: 2 }),
             operand2: None,
             operand3: None,
+            arguments: vec![],
         };
         assert_eq!(Ok(("", expected)), actual);
     }
+
+    #[test]
+    fn test_parse_with_label() {
+        let actual = instruction("loop: jmp @loop\n");
+        let expected = Instruction {
+            opcode: Some(Token::Op { code: Opcode::JMP }),
+            label: Some(Token::LabelDecl {
+                name: "loop".to_string(),
+            }),
+            directive: None,
+            operand1: Some(Token::LabelUsage {
+                name: "loop".to_string(),
+            }),
+            operand2: None,
+            operand3: None,
+            arguments: vec![],
+        };
+        assert_eq!(Ok(("", expected)), actual);
+    }
+
+    #[test]
+    fn test_instruction_to_bytes() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("done", SectionKind::Code, 300);
+
+        let (_, instr) = instruction("jeq @done\n").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 3);
+        let bytes = instr.to_bytes(&symbols).unwrap();
+        assert_eq!(bytes, vec![Opcode::JEQI.into(), 1, 44]);
+
+        let (_, instr) = instruction("jeq $1\n").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 2);
+        let bytes = instr.to_bytes(&symbols).unwrap();
+        assert_eq!(bytes, vec![Opcode::JEQ.into(), 1]);
+
+        let (_, instr) = instruction("jmp @nowhere\n").unwrap();
+        assert!(instr.to_bytes(&symbols).is_err());
+
+        let (_, instr) = instruction("loadf64 $1 #2").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 10);
+        let bytes = instr.to_bytes(&symbols).unwrap();
+        assert_eq!(bytes[..2], [Opcode::LOADF64.into(), 1]);
+        assert_eq!(bytes[2..], 2.0f64.to_be_bytes());
+    }
+
+    #[test]
+    fn test_instruction_invalid_operands() {
+        let symbols = SymbolTable::new();
+        for code in &["add $0 #1 $2", "load #1 $0", "load $0 #1.5"] {
+            let (_, instr) = instruction(code).unwrap();
+            assert!(
+                matches!(
+                    instr.to_bytes(&symbols),
+                    Err(ParsingError::InvalidOperands(_))
+                ),
+                "{}",
+                code
+            );
+            assert!(instr.encoded_len().is_err());
+        }
+    }
+
+    #[test]
+    fn test_instruction_operand_checks() {
+        let symbols = SymbolTable::new();
+        for code in &["add $0 $1", "hlt $1", "jmp"] {
+            let (_, instr) = instruction(code).unwrap();
+            assert!(
+                matches!(
+                    instr.to_bytes(&symbols),
+                    Err(ParsingError::WrongOperandCount { .. })
+                ),
+                "{}",
+                code
+            );
+        }
+
+        let (_, instr) = instruction("load $0 #-32768").unwrap();
+        assert_eq!(
+            instr.to_bytes(&symbols).unwrap(),
+            vec![Opcode::LOAD.into(), 0, 0x80, 0]
+        );
+        let (_, instr) = instruction("load $0 #32768").unwrap();
+        assert!(matches!(
+            instr.to_bytes(&symbols),
+            Err(ParsingError::InvalidOperand(TokenError::OutOfRange(
+                32768,
+                _
+            )))
+        ));
+        let (_, instr) = instruction("loadw $0 #-70000").unwrap();
+        assert_eq!(
+            instr.to_bytes(&symbols).unwrap(),
+            [&[Opcode::LOADW.into(), 0][..], &(-70000i32).to_be_bytes()].concat()
+        );
+        let (_, instr) = instruction("jmp #-1").unwrap();
+        assert!(matches!(
+            instr.to_bytes(&symbols),
+            Err(ParsingError::InvalidOperand(TokenError::OutOfRange(-1, _)))
+        ));
+
+        assert!(instruction("load $0 #1 $2 $3").is_err());
+        assert!(instruction("load $32 #1").is_err());
+        assert!(instruction("daol $1").is_err());
+    }
+
+    #[test]
+    fn test_parse_directive() {
+        let actual = instruction("hello: .asciiz \"Hi\" ; greeting\n");
+        let expected = Instruction {
+            opcode: None,
+            label: Some(Token::LabelDecl {
+                name: "hello".to_string(),
+            }),
+            directive: Some(Token::Directive {
+                name: "asciiz".to_string(),
+            }),
+            operand1: None,
+            operand2: None,
+            operand3: None,
+            arguments: vec![Token::IrString {
+                value: "Hi".to_string(),
+            }],
+        };
+        assert_eq!(Ok(("", expected)), actual);
+        assert!(instruction(".data").is_ok());
+    }
+
+    #[test]
+    fn test_directive_to_bytes() {
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol("main", SectionKind::Code, 258);
+
+        let (_, instr) = instruction(".asciiz \"Hi\"").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 3);
+        assert_eq!(instr.to_bytes(&symbols).unwrap(), b"Hi\0".to_vec());
+
+        let (_, instr) = instruction(".integer #1 @main").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 8);
+        let bytes = instr.to_bytes(&symbols).unwrap();
+        assert_eq!(bytes, vec![0, 0, 0, 1, 0, 0, 1, 2]);
+
+        let (_, instr) = instruction(".bytes #1 #255 #2").unwrap();
+        assert_eq!(instr.encoded_len().unwrap(), 3);
+        assert_eq!(instr.to_bytes(&symbols).unwrap(), vec![1, 255, 2]);
+
+        let (_, instr) = instruction(".bytes #256").unwrap();
+        assert!(instr.to_bytes(&symbols).is_err());
+
+        let (_, instr) = instruction(".asciiz #1").unwrap();
+        assert!(instr.to_bytes(&symbols).is_err());
+
+        let (_, instr) = instruction(".unknown").unwrap();
+        assert!(instr.to_bytes(&symbols).is_err());
+    }
 }
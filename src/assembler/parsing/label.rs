@@ -1,12 +1,29 @@
-use nom::{character::complete::alphanumeric1, do_parse, named, tag};
+use nom::{
+    alt,
+    character::complete::{alpha1, alphanumeric1},
+    complete, do_parse, many0, map, named, pair, recognize, tag,
+};
 
 use crate::assembler::token::Token;
 
+named!(
+    pub identifier<&str, &str>,
+    recognize!(pair!(
+        alt!(alpha1 | complete!(tag!("_"))),
+        many0!(alt!(alphanumeric1 | complete!(tag!("_"))))
+    ))
+);
+
+named!(
+    pub symbol<&str, Token>,
+    map!(identifier, |name| Token::Symbol { name: name.to_string() })
+);
+
 named!(
     pub label_decl<&str, Token>,
     do_parse!(
-        name: alphanumeric1 >>
-        tag!(":") >>
+        name: identifier >>
+        complete!(tag!(":")) >>
         (
             Token::LabelDecl { name: name.to_string() }
         )
@@ -16,8 +33,8 @@ named!(
 named!(
     pub label_usage<&str, Token>,
     do_parse!(
-        tag!("@") >>
-        name: alphanumeric1 >>
+        complete!(tag!("@")) >>
+        name: identifier >>
         (
             Token::LabelUsage { name: name.to_string() }
         )
@@ -28,7 +45,7 @@ named!(
 mod tests {
     use crate::assembler::{parsing::label::label_usage, token::Token};
 
-    use super::label_decl;
+    use super::{identifier, label_decl};
 
     #[test]
     fn test_parse_label_decl() {
@@ -42,6 +59,14 @@ mod tests {
         assert_eq!(rest, "");
     }
 
+    #[test]
+    fn test_parse_identifier() {
+        assert_eq!(identifier("MAX_LEN2 x"), Ok((" x", "MAX_LEN2")));
+        assert_eq!(identifier("_tmp"), Ok(("", "_tmp")));
+        assert!(identifier("2x").is_err());
+        assert!(label_decl("loop_end:").is_ok());
+    }
+
     #[test]
     fn test_parse_label_usage() {
         let result = label_usage("@bar");
@@ -1,15 +1,22 @@
 mod comment;
+mod directive;
 mod error;
+mod expression;
 mod instruction;
 mod label;
 mod opcode;
 mod operand;
 mod program;
 mod register;
+mod string;
 
+pub use directive::directive;
 pub use error::ParsingError;
+pub use expression::{expression, expression_operand};
 pub use instruction::{instruction, Instruction};
+pub use label::{identifier, label_decl, label_usage, symbol};
 pub use opcode::opcode;
-pub use operand::number;
+pub use operand::{float, number, operand, parse_integer};
 pub use program::{program, Program};
 pub use register::register;
+pub use string::string;
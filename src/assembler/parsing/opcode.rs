@@ -1,12 +1,12 @@
 use crate::assembler::token::Token;
 use crate::instruction::Opcode;
-use nom::character::complete::alpha1;
-use nom::{do_parse, named};
+use nom::character::complete::alphanumeric1;
+use nom::{do_parse, named, verify};
 
 named!(
     pub opcode<&str, Token>,
     do_parse!(
-        op: alpha1 >>
+        op: verify!(alphanumeric1, |op: &str| Opcode::from(op) != Opcode::IGL) >>
         (
             Token::Op { code: Opcode::from(op) }
         )
@@ -25,8 +25,16 @@ mod tests {
         assert_eq!(token, Token::Op { code: Opcode::LOAD });
         assert_eq!(rest, "");
 
-        let (rest, token) = opcode("daol").unwrap();
-        assert_eq!(token, Token::Op { code: Opcode::IGL });
+        let (rest, token) = opcode("loadf64").unwrap();
+        assert_eq!(
+            token,
+            Token::Op {
+                code: Opcode::LOADF64
+            }
+        );
         assert_eq!(rest, "");
+
+        assert!(opcode("daol").is_err());
+        assert!(opcode("igl").is_err());
     }
 }
@@ -1,23 +1,116 @@
 use crate::assembler::token::Token;
-use nom::character::complete::digit1;
-use nom::{alt, do_parse, named, tag};
+use nom::character::complete::{alphanumeric1, anychar};
+use nom::number::complete::recognize_float;
+use nom::{
+    alt, char, complete, delimited, do_parse, many1, map, map_opt, map_res, named, opt, pair,
+    preceded, recognize, tag, verify,
+};
+use std::num::IntErrorKind;
 
-use super::register;
+use super::{expression::expression_operand, label::label_usage, register};
+
+/// Parses an integer literal without the leading `#`.
+///
+/// Decimal literals denote `i32` values. Hexadecimal (`0x`) and binary
+/// (`0b`) ones denote 32-bit patterns, so `0xFFFFFFFF` is -1.
+/// Any literal may be negated and have underscores between digits.
+pub fn parse_integer(text: &str) -> Result<i32, IntErrorKind> {
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, text),
+    };
+    let prefix = text.get(..2).map(str::to_ascii_lowercase);
+    let (radix, digits) = match prefix.as_deref() {
+        Some("0x") => (16, &text[2..]),
+        Some("0b") => (2, &text[2..]),
+        _ => (10, text),
+    };
+    if digits.is_empty() {
+        return Err(IntErrorKind::Empty);
+    }
+    if digits.starts_with('_') || digits.ends_with('_') || digits.contains("__") {
+        return Err(IntErrorKind::InvalidDigit);
+    }
+    let digits: String = digits.chars().filter(|c| *c != '_').collect();
+    if !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(IntErrorKind::InvalidDigit);
+    }
+    let magnitude = u64::from_str_radix(&digits, radix).map_err(|e| *e.kind())?;
+    let limit = match (negative, radix) {
+        (true, _) => i32::MAX as u64 + 1,
+        (false, 10) => i32::MAX as u64,
+        (false, _) => u32::MAX as u64,
+    };
+    match magnitude {
+        m if m > limit && negative => Err(IntErrorKind::NegOverflow),
+        m if m > limit => Err(IntErrorKind::PosOverflow),
+        m if negative => Ok((m as i64).wrapping_neg() as i32),
+        m => Ok(m as u32 as i32),
+    }
+}
+
+/// Maps a character following a backslash to the character it denotes.
+fn unescape(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        '0' => Some('\0'),
+        '\\' | '\'' => Some(c),
+        _ => None,
+    }
+}
+
+named!(
+    pub char_literal<&str, i32>,
+    map!(
+        delimited!(
+            complete!(char!('\'')),
+            alt!(
+                preceded!(complete!(char!('\\')), map_opt!(anychar, unescape)) |
+                verify!(anychar, |c: &char| c.is_ascii() && *c != '\'' && *c != '\\')
+            ),
+            complete!(char!('\''))
+        ),
+        |c: char| c as i32
+    )
+);
+
+named!(
+    pub integer_literal<&str, i32>,
+    map_res!(
+        recognize!(pair!(
+            opt!(complete!(char!('-'))),
+            many1!(alt!(alphanumeric1 | complete!(tag!("_"))))
+        )),
+        parse_integer
+    )
+);
 
 named!(
     pub number<&str, Token>,
     do_parse!(
-        tag!("#") >>
-        int_num: digit1 >>
+        complete!(tag!("#")) >>
+        value: alt!(char_literal | integer_literal) >>
+        (
+            Token::Number { value }
+        )
+    )
+);
+
+named!(
+    pub float<&str, Token>,
+    do_parse!(
+        complete!(tag!("#")) >>
+        float_num: verify!(recognize_float, |s: &str| s.contains('.')) >>
         (
-            Token::Number { value: int_num.parse::<i32>().unwrap() }
+            Token::Float { value: float_num.parse::<f64>().unwrap() }
         )
     )
 );
 
 named!(
     pub operand<&str, Token>,
-    alt!(number | register)
+    alt!(float | number | expression_operand | register | label_usage)
 );
 
 #[cfg(test)]
@@ -28,9 +121,11 @@ mod tests {
     fn test_parse_operand() {
         let reg_result = operand("$0");
         let num_result = operand("#100");
+        let label_result = operand("@loop");
 
         assert!(reg_result.is_ok());
         assert!(num_result.is_ok());
+        assert!(label_result.is_ok());
     }
 
     #[test]
@@ -43,5 +138,64 @@ mod tests {
 
         let result = number("10");
         assert!(result.is_err());
+        assert!(number("#99999999999").is_err());
+        assert_eq!(number("#-5"), Ok(("", Token::Number { value: -5 })));
+        assert_eq!(
+            number("#-2147483648"),
+            Ok(("", Token::Number { value: i32::MIN }))
+        );
+        assert!(number("#-").is_err());
+    }
+
+    #[test]
+    fn test_parse_number_literals() {
+        for (literal, expected) in &[
+            ("#0xFF", 255),
+            ("#0Xff", 255),
+            ("#-0x10", -16),
+            ("#0b1010", 10),
+            ("#0b1111_0000", 240),
+            ("#1_000_000", 1_000_000),
+            ("#0xFFFFFFFF", -1),
+            ("#-2147483648", i32::MIN),
+            ("#'A'", 65),
+            ("#' '", 32),
+            ("#'\\n'", 10),
+            ("#'\\''", 39),
+            ("#'\\\\'", 92),
+        ] {
+            let value = *expected;
+            assert_eq!(
+                number(literal),
+                Ok(("", Token::Number { value })),
+                "{}",
+                literal
+            );
+        }
+        for literal in &[
+            "#0x", "#0b102", "#1__0", "#_1", "#1_", "#0xG", "#12ab", "#''", "#'ab'", "#'\\q'",
+        ] {
+            assert!(number(literal).is_err(), "{}", literal);
+        }
+
+        assert_eq!(parse_integer("2147483648"), Err(IntErrorKind::PosOverflow));
+        assert_eq!(
+            parse_integer("0x1_0000_0000"),
+            Err(IntErrorKind::PosOverflow)
+        );
+        assert_eq!(parse_integer("-2147483649"), Err(IntErrorKind::NegOverflow));
+        assert_eq!(parse_integer("+1"), Err(IntErrorKind::InvalidDigit));
+    }
+
+    #[test]
+    fn test_parse_float() {
+        let result = float("#3.25");
+        assert_eq!(result, Ok(("", Token::Float { value: 3.25 })));
+        let result = float("#-0.5e2");
+        assert_eq!(result, Ok(("", Token::Float { value: -50.0 })));
+        assert!(float("#10").is_err());
+
+        let result = operand("#10");
+        assert_eq!(result, Ok(("", Token::Number { value: 10 })));
     }
 }
@@ -1,7 +1,7 @@
-use nom::character::complete::line_ending;
-use nom::{do_parse, named, separated_list1};
+use nom::{complete, do_parse, many1, named};
 
-use crate::assembler::parsing::{instruction, Instruction};
+use crate::assembler::parsing::{instruction, Instruction, ParsingError};
+use crate::assembler::symbols::SymbolTable;
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
@@ -9,19 +9,23 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Result<Vec<u8>, ParsingError> {
         let mut bytes = vec![];
         for instr in &self.instructions {
-            bytes.append(&mut instr.to_bytes().unwrap());
+            bytes.append(&mut instr.to_bytes(symbols)?);
         }
-        bytes
+        Ok(bytes)
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
     }
 }
 
 named!(
     pub program<&str, Program>,
     do_parse!(
-        instructions: separated_list1!(line_ending, instruction) >>
+        instructions: many1!(complete!(instruction)) >>
         (
             Program { instructions }
         )
@@ -39,7 +43,16 @@ mod tests {
         let (rest, p) = result.unwrap();
         assert_eq!(rest, "");
         assert_eq!(1, p.instructions.len());
-        assert_eq!(p.to_bytes().len(), 4);
+        assert_eq!(p.to_bytes(&SymbolTable::new()).unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_parse_program_without_trailing_newline() {
+        let result = program("load $0 #100\nhlt");
+        assert!(result.is_ok());
+        let (rest, p) = result.unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(2, p.instructions.len());
     }
 
     #[test]
@@ -49,8 +62,10 @@ mod tests {
             add $0 #10  ; add 10 to reg 0
         ";
         let result = program(code);
-        // assert!(result.is_ok());
-        // let (rest, p) = result.unwrap();
+        assert!(result.is_ok());
+        let (rest, p) = result.unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(2, p.instructions.len());
     }
 
     #[test]
@@ -58,7 +73,7 @@ mod tests {
         let result = program("load $0 #100\n");
         assert!(result.is_ok());
         let (_, program) = result.unwrap();
-        let bytecode = program.to_bytes();
+        let bytecode = program.to_bytes(&SymbolTable::new()).unwrap();
         assert_eq!(bytecode.len(), 4);
     }
 }
@@ -1,15 +1,17 @@
 use crate::assembler::token::Token;
-use nom::{character::complete::digit1, do_parse, named, tag};
+use crate::instruction::REGISTER_COUNT;
+use nom::{character::complete::digit1, complete, do_parse, map_res, named, tag, verify};
 
 named!(
     pub register<&str, Token>,
     do_parse!(
-        tag!("$") >>
-        reg_num: digit1 >>
+        complete!(tag!("$")) >>
+        reg_num: verify!(
+            map_res!(digit1, |s: &str| s.parse::<u8>()),
+            |n: &u8| (*n as usize) < REGISTER_COUNT
+        ) >>
         (
-            Token::Register {
-                reg_num: reg_num.parse::<u8>().unwrap()
-            }
+            Token::Register { reg_num }
         )
     )
 );
@@ -26,5 +28,8 @@ mod tests {
         assert!(result.is_err());
         let result = register("$a");
         assert!(result.is_err());
+        assert_eq!(register("$31"), Ok(("", Token::Register { reg_num: 31 })));
+        assert!(register("$32").is_err());
+        assert!(register("$300").is_err());
     }
 }
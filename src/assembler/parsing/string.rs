@@ -0,0 +1,53 @@
+use nom::{alt, complete, do_parse, escaped_transform, is_not, named, opt, tag};
+
+use crate::assembler::token::Token;
+
+named!(
+    pub string<&str, Token>,
+    do_parse!(
+        complete!(tag!("\"")) >>
+        value: opt!(escaped_transform!(
+            is_not!("\\\""),
+            '\\',
+            alt!(
+                tag!("\\") => { |_| "\\" } |
+                tag!("\"") => { |_| "\"" } |
+                tag!("n") => { |_| "\n" } |
+                tag!("t") => { |_| "\t" } |
+                tag!("0") => { |_| "\0" }
+            )
+        )) >>
+        complete!(tag!("\"")) >>
+        (
+            Token::IrString { value: value.unwrap_or_default() }
+        )
+    )
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_string() {
+        let result = string("\"Hello, world\"");
+        let expected = Token::IrString {
+            value: "Hello, world".to_string(),
+        };
+        assert_eq!(result, Ok(("", expected)));
+
+        let result = string("\"say \\\"hi\\\"\\n\" rest");
+        let expected = Token::IrString {
+            value: "say \"hi\"\n".to_string(),
+        };
+        assert_eq!(result, Ok((" rest", expected)));
+
+        let result = string("\"\"");
+        let expected = Token::IrString {
+            value: String::new(),
+        };
+        assert_eq!(result, Ok(("", expected)));
+
+        assert!(string("\"unterminated").is_err());
+    }
+}
@@ -0,0 +1,793 @@
+use crate::assembler::{
+    diagnostic::Note,
+    parsing::{identifier, label_decl, string},
+    token::Token,
+    AssemblerError, Diagnostic, Diagnostics, Span,
+};
+use crate::instruction::Opcode;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Maximum number of macro calls expanded in a single source.
+const MAX_EXPANSIONS: usize = 10_000;
+
+/// Maximum nesting of `.include` directives.
+const MAX_INCLUDE_DEPTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MacroError {
+    /// Header of a `.macro` directive is malformed, contains the reason.
+    InvalidDefinition(String),
+    DuplicateMacro(String),
+    /// `.macro` without the matching `.endm`.
+    Unterminated(String),
+    /// `.endm` without the matching `.macro`.
+    UnexpectedEnd,
+    WrongArgumentCount {
+        name: String,
+        expected: usize,
+        found: usize,
+    },
+    Recursion(String),
+    /// Source expands more than `MAX_EXPANSIONS` macro calls.
+    TooManyExpansions,
+}
+
+impl Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MacroError::InvalidDefinition(reason) => {
+                write!(f, "Invalid macro definition: {}", reason)
+            }
+            MacroError::DuplicateMacro(name) => {
+                write!(f, "Macro defined more than once: {}", name)
+            }
+            MacroError::Unterminated(name) => {
+                write!(f, "Macro {} is not closed with .endm", name)
+            }
+            MacroError::UnexpectedEnd => write!(f, "Unexpected .endm outside of a macro"),
+            MacroError::WrongArgumentCount {
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "Macro {} expects {} argument(s), found {}",
+                name, expected, found
+            ),
+            MacroError::Recursion(name) => {
+                write!(f, "Macro {} is expanded recursively", name)
+            }
+            MacroError::TooManyExpansions => {
+                write!(f, "More than {} macro calls to expand", MAX_EXPANSIONS)
+            }
+        }
+    }
+}
+
+impl Error for MacroError {}
+
+/// Macro defined with `.macro name params...` and `.endm`.
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    /// Labels declared in the body, they are renamed on every expansion.
+    locals: Vec<String>,
+    /// Body lines along with their locations.
+    body: Vec<(Location, String)>,
+}
+
+/// Line of one of the source files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Location {
+    /// Index of the file in the expansion.
+    file: usize,
+    line: usize,
+}
+
+/// Place a line of the expanded text comes from.
+#[derive(Debug, Clone, PartialEq)]
+enum Origin {
+    /// Source line copied as is.
+    Source(Location),
+    /// Line of a macro body, along with the names of the macros
+    /// and the lines they were called on, innermost call first.
+    Macro {
+        body: Location,
+        calls: Vec<(String, Location)>,
+    },
+}
+
+impl Origin {
+    fn location(&self) -> Location {
+        match self {
+            Origin::Source(location) | Origin::Macro { body: location, .. } => *location,
+        }
+    }
+
+    fn calls(&self) -> &[(String, Location)] {
+        match self {
+            Origin::Source(_) => &[],
+            Origin::Macro { calls, .. } => calls,
+        }
+    }
+}
+
+/// Source file taking part in the expansion.
+#[derive(Debug)]
+struct SourceFile {
+    /// Path of an included file, the main source has none.
+    name: Option<String>,
+    text: String,
+    /// Directory the includes of the file are relative to.
+    dir: PathBuf,
+}
+
+/// Source with all the includes and macro calls replaced by their text.
+#[derive(Debug)]
+pub(super) struct Expansion {
+    files: Vec<SourceFile>,
+    pub text: String,
+    /// Origin of every line of the text.
+    origins: Vec<Origin>,
+}
+
+impl Expansion {
+    /// Creates a diagnostic for a span of the expanded text,
+    /// pointing at the original source.
+    ///
+    /// Errors within a macro expansion point at the line of the body,
+    /// with a note for every macro call that led to it.
+    pub fn diagnostic(&self, error: AssemblerError, span: Span) -> Diagnostic {
+        let mut diagnostic = match self.origins.get(span.line - 1) {
+            Some(Origin::Source(location)) => {
+                let file = &self.files[location.file];
+                let mut diagnostic = Diagnostic::new(
+                    error,
+                    &file.text,
+                    Span {
+                        line: location.line,
+                        ..span
+                    },
+                );
+                diagnostic.file = file.name.clone();
+                diagnostic
+            }
+            Some(origin) => self.origin_diagnostic(error, origin),
+            None => Diagnostic::new(error, &self.files[0].text, span),
+        };
+        diagnostic.position = (span.line, span.column);
+        diagnostic
+    }
+
+    /// Creates a diagnostic spanning the whole line the origin refers to.
+    fn origin_diagnostic(&self, error: AssemblerError, origin: &Origin) -> Diagnostic {
+        let (text, span) = self.line_span(origin.location());
+        let mut diagnostic = Diagnostic::new(error, text, span);
+        diagnostic.file = self.files[origin.location().file].name.clone();
+        for (name, call) in origin.calls() {
+            let message = format!("in expansion of macro {}", name);
+            let (text, span) = self.line_span(*call);
+            let mut note = Note::new(message, text, span);
+            note.file = self.files[call.file].name.clone();
+            diagnostic.notes.push(note);
+        }
+        diagnostic
+    }
+
+    /// Returns the file index, line and column in the source files
+    /// the span of the expanded text comes from.
+    ///
+    /// Lines of macro bodies map to the outermost macro call,
+    /// the line written in the file being assembled.
+    pub fn source_location(&self, span: Span) -> (usize, usize, usize) {
+        match self.origins.get(span.line - 1) {
+            Some(Origin::Source(location)) => (location.file, location.line, span.column),
+            Some(origin) => {
+                let call = origin
+                    .calls()
+                    .last()
+                    .map_or(origin.location(), |(_, call)| *call);
+                let (_, span) = self.line_span(call);
+                (call.file, call.line, span.column)
+            }
+            None => (0, span.line, span.column),
+        }
+    }
+
+    /// Returns the paths of the included files in the order
+    /// of their indices, the main source has none.
+    pub fn file_names(&self) -> impl Iterator<Item = Option<&str>> {
+        self.files.iter().map(|file| file.name.as_deref())
+    }
+
+    /// Returns a text of the file and a span of the code
+    /// on the given line, without the comment.
+    fn line_span(&self, location: Location) -> (&str, Span) {
+        let source = &self.files[location.file].text;
+        let text = source.lines().nth(location.line - 1).unwrap_or_default();
+        let code = text.split(';').next().unwrap_or_default();
+        let start = code.len() - code.trim_start().len();
+        let span = Span {
+            line: location.line,
+            column: text[..start].chars().count() + 1,
+            len: code.trim().chars().count(),
+        };
+        (source, span)
+    }
+}
+
+/// Expands includes and macros in the source, before it gets parsed.
+///
+/// `.include "path"` inserts the text of the file, the path is relative
+/// to the directory of the including file, `dir` for the main source.
+///
+/// A macro is called by its name followed by whitespace separated
+/// arguments, which replace `\param` references within the body.
+/// Labels declared in the body are unique to every expansion.
+pub(super) fn expand(source: &str, dir: &Path, diagnostics: &mut Diagnostics) -> Expansion {
+    let mut expander = Expander {
+        expansion: Expansion {
+            files: vec![SourceFile {
+                name: None,
+                text: source.to_string(),
+                dir: dir.to_path_buf(),
+            }],
+            text: String::new(),
+            origins: vec![],
+        },
+        macros: HashMap::new(),
+        expansions: 0,
+        errors: vec![],
+    };
+    expander.expand_file(0, &mut vec![]);
+    let Expander {
+        expansion, errors, ..
+    } = expander;
+    for (error, origin, line) in errors {
+        let mut diagnostic = expansion.origin_diagnostic(error, &origin);
+        diagnostic.position = (line, 0);
+        diagnostics.push(diagnostic);
+    }
+    expansion
+}
+
+struct Expander {
+    expansion: Expansion,
+    macros: HashMap<String, Macro>,
+    /// Number of expansions so far, makes local labels unique.
+    expansions: usize,
+    /// Errors along with their origins and the lines of the expanded text they precede.
+    errors: Vec<(AssemblerError, Origin, usize)>,
+}
+
+impl Expander {
+    /// Expands the lines of a file, `open` are the paths of the files
+    /// being included, which must not be included again.
+    fn expand_file(&mut self, file: usize, open: &mut Vec<PathBuf>) {
+        let source = self.expansion.files[file].text.clone();
+        let mut lines = source.lines().zip(1..).map(|(text, line)| {
+            let location = Location { file, line };
+            (text, location)
+        });
+        while let Some((text, location)) = lines.next() {
+            match first_word(text) {
+                ".include" => self.include(text, location, open),
+                ".macro" => {
+                    let mut body = vec![];
+                    let mut closed = false;
+                    for (text, location) in &mut lines {
+                        match first_word(text) {
+                            ".endm" => {
+                                closed = true;
+                                break;
+                            }
+                            ".macro" => {
+                                let reason = "nested definition".to_string();
+                                let error = MacroError::InvalidDefinition(reason);
+                                self.error(error.into(), Origin::Source(location));
+                            }
+                            _ => body.push((location, text.to_string())),
+                        }
+                    }
+                    self.define(text, location, body, closed);
+                }
+                ".endm" => {
+                    let error = MacroError::UnexpectedEnd.into();
+                    self.error(error, Origin::Source(location));
+                }
+                _ => self.expand_line(text, Origin::Source(location)),
+            }
+        }
+    }
+
+    /// Expands the file included by the `.include "path"` line.
+    fn include(&mut self, text: &str, location: Location, open: &mut Vec<PathBuf>) {
+        let code = text.split(';').next().unwrap_or_default().trim();
+        let argument = code[".include".len()..].trim_start();
+        let fail = |path: &str, reason: &str| AssemblerError::Include {
+            path: path.to_string(),
+            reason: reason.to_string(),
+        };
+        let path = match string(argument) {
+            Ok(("", Token::IrString { value })) => value,
+            _ => {
+                let error = fail(argument, "expected a quoted path");
+                return self.error(error, Origin::Source(location));
+            }
+        };
+        // Canonical paths catch the cycles spelled differently
+        let full_path = match fs::canonicalize(self.expansion.files[location.file].dir.join(&path))
+        {
+            Ok(full_path) => full_path,
+            Err(e) => return self.error(fail(&path, &e.to_string()), Origin::Source(location)),
+        };
+        if open.contains(&full_path) {
+            let error = fail(&path, "file includes itself");
+            return self.error(error, Origin::Source(location));
+        }
+        if open.len() == MAX_INCLUDE_DEPTH {
+            let error = fail(&path, "includes are nested too deeply");
+            return self.error(error, Origin::Source(location));
+        }
+        let text = match fs::read_to_string(&full_path) {
+            Ok(text) => text,
+            Err(e) => return self.error(fail(&path, &e.to_string()), Origin::Source(location)),
+        };
+        let dir = full_path
+            .parent()
+            .map(Path::to_path_buf)
+            .unwrap_or_default();
+        self.expansion.files.push(SourceFile {
+            name: Some(full_path.to_string_lossy().into_owned()),
+            text,
+            dir,
+        });
+        open.push(full_path);
+        self.expand_file(self.expansion.files.len() - 1, open);
+        open.pop();
+    }
+
+    /// Records a macro from its `.macro` header and body lines.
+    fn define(
+        &mut self,
+        header: &str,
+        location: Location,
+        body: Vec<(Location, String)>,
+        closed: bool,
+    ) {
+        let origin = Origin::Source(location);
+        let code = header.split(';').next().unwrap_or_default();
+        let mut words = code.split_whitespace().skip(1);
+        let name = match words.next() {
+            Some(name) if is_identifier(name) => name.to_string(),
+            Some(name) => {
+                let reason = format!("{} is not a valid name", name);
+                let error = MacroError::InvalidDefinition(reason);
+                return self.error(error.into(), origin);
+            }
+            None => {
+                let error = MacroError::InvalidDefinition("missing name".to_string());
+                return self.error(error.into(), origin);
+            }
+        };
+        if !closed {
+            return self.error(MacroError::Unterminated(name).into(), origin);
+        }
+        if Opcode::from(name.as_str()) != Opcode::IGL || name == "igl" {
+            let reason = format!("{} is an opcode", name);
+            let error = MacroError::InvalidDefinition(reason);
+            return self.error(error.into(), origin);
+        }
+        if self.macros.contains_key(&name) {
+            return self.error(MacroError::DuplicateMacro(name).into(), origin);
+        }
+        let mut params: Vec<String> = vec![];
+        for param in words {
+            if !is_identifier(param) || params.iter().any(|p| p == param) {
+                let reason = format!("invalid parameter {}", param);
+                let error = MacroError::InvalidDefinition(reason);
+                return self.error(error.into(), origin);
+            }
+            params.push(param.to_string());
+        }
+        let locals = body
+            .iter()
+            .filter_map(|(_, text)| match label_decl(first_word(text)) {
+                Ok(("", _)) => Some(first_word(text).trim_end_matches(':').to_string()),
+                _ => None,
+            })
+            .collect();
+        let definition = Macro {
+            params,
+            locals,
+            body,
+        };
+        self.macros.insert(name, definition);
+    }
+
+    /// Appends a line to the expanded text, expanding a macro call.
+    fn expand_line(&mut self, text: &str, origin: Origin) {
+        let code = text.split(';').next().unwrap_or_default();
+        // Head of the line is always at the start of `rest`
+        let mut rest = code.trim_start();
+        let mut head = first_word(rest);
+        let mut label = None;
+        if matches!(label_decl(head), Ok(("", _))) {
+            label = Some(head);
+            rest = rest[head.len()..].trim_start();
+            head = first_word(rest);
+        }
+        let definition = match self.macros.get(head) {
+            Some(definition) => definition,
+            None => return self.push(text.to_string(), origin),
+        };
+        if origin.calls().iter().any(|(name, _)| name == head) {
+            let error = MacroError::Recursion(head.to_string());
+            return self.error(error.into(), origin);
+        }
+        if self.expansions >= MAX_EXPANSIONS {
+            // Reported once, the remaining calls are dropped
+            if self.expansions == MAX_EXPANSIONS {
+                self.expansions += 1;
+                self.error(MacroError::TooManyExpansions.into(), origin);
+            }
+            return;
+        }
+        let args = split_arguments(&rest[head.len()..]);
+        if args.len() != definition.params.len() {
+            let error = MacroError::WrongArgumentCount {
+                name: head.to_string(),
+                expected: definition.params.len(),
+                found: args.len(),
+            };
+            return self.error(error.into(), origin);
+        }
+
+        let expansion = self.expansions + 1;
+        let lines: Vec<_> = definition
+            .body
+            .iter()
+            .map(|(location, text)| (substitute(text, definition, &args, expansion), *location))
+            .collect();
+        self.expansions = expansion;
+        if let Some(label) = label {
+            self.push(label.to_string(), origin.clone());
+        }
+        let mut calls = vec![(head.to_string(), origin.location())];
+        calls.extend_from_slice(origin.calls());
+        for (text, body) in lines {
+            let origin = Origin::Macro {
+                body,
+                calls: calls.clone(),
+            };
+            self.expand_line(&text, origin);
+        }
+    }
+
+    fn push(&mut self, text: String, origin: Origin) {
+        self.expansion.text.push_str(&text);
+        self.expansion.text.push('\n');
+        self.expansion.origins.push(origin);
+    }
+
+    fn error(&mut self, error: AssemblerError, origin: Origin) {
+        let line = self.expansion.origins.len() + 1;
+        self.errors.push((error, origin, line));
+    }
+}
+
+fn first_word(text: &str) -> &str {
+    text.split_whitespace().next().unwrap_or_default()
+}
+
+fn is_identifier(word: &str) -> bool {
+    matches!(identifier(word), Ok(("", _)))
+}
+
+/// Splits call arguments on whitespace outside of parentheses and quotes,
+/// so that `#(A + 1)` is a single argument.
+fn split_arguments(text: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for c in text.chars() {
+        match (quote, c) {
+            (Some(_), _) if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '"') | (None, '\'') => quote = Some(c),
+            (None, '(') => depth += 1,
+            (None, ')') => depth -= 1,
+            (None, c) if c.is_whitespace() && depth == 0 => {
+                if !current.is_empty() {
+                    args.push(std::mem::take(&mut current));
+                }
+                continue;
+            }
+            _ => {}
+        }
+        current.push(c);
+    }
+    if !current.is_empty() {
+        args.push(current);
+    }
+    args
+}
+
+/// Replaces `\param` references with the arguments and renames
+/// the local labels, outside of string and character literals.
+fn substitute(text: &str, definition: &Macro, args: &[String], expansion: usize) -> String {
+    let mut result = String::new();
+    let mut rest = text;
+    let mut quote = None;
+    while let Some(c) = rest.chars().next() {
+        if let Some(q) = quote {
+            if c == '\\' && rest.len() > 1 {
+                let len = 1 + rest[1..].chars().next().map_or(0, char::len_utf8);
+                result.push_str(&rest[..len]);
+                rest = &rest[len..];
+                continue;
+            }
+            if c == q {
+                quote = None;
+            }
+        } else if c == '"' || c == '\'' {
+            quote = Some(c);
+        } else if c == ';' {
+            result.push_str(rest);
+            break;
+        } else if c == '\\' || c == '@' || c.is_alphabetic() || c == '_' {
+            let start = if c == '\\' || c == '@' { 1 } else { 0 };
+            if let Ok((after, name)) = identifier(&rest[start..]) {
+                let param = definition.params.iter().position(|p| p == name);
+                let local = definition.locals.iter().any(|l| l == name);
+                let declared = start == 0 && after.starts_with(':');
+                match (c, param) {
+                    ('\\', Some(i)) => result.push_str(&args[i]),
+                    _ if local && (c == '@' || declared) => {
+                        result.push_str(&rest[..start]);
+                        result.push_str(&format!("__{}_{}", name, expansion));
+                    }
+                    _ => result.push_str(&rest[..rest.len() - after.len()]),
+                }
+                rest = after;
+                continue;
+            }
+        }
+        result.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand_ok(source: &str) -> String {
+        let mut diagnostics = Diagnostics::default();
+        let expansion = expand(source, Path::new(""), &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{}", diagnostics);
+        expansion.text
+    }
+
+    #[test]
+    fn test_expand() {
+        let source = "
+.macro swap a b
+    store \\a $31 ; 'a' stays
+    loop: jmp @loop
+.endm
+start: swap $1 #(1 + 2)
+    swap $3 $4
+";
+        assert_eq!(
+            expand_ok(source),
+            "\nstart:\n    store $1 $31 ; 'a' stays\n    __loop_1: jmp @__loop_1\n    \
+             store $3 $31 ; 'a' stays\n    __loop_2: jmp @__loop_2\n"
+        );
+    }
+
+    #[test]
+    fn test_expand_nested() {
+        let source = "
+.macro inner x
+    inc \\x
+.endm
+.macro outer x
+    inner \\x
+    inner \\x
+.endm
+outer $2
+";
+        let mut diagnostics = Diagnostics::default();
+        let expansion = expand(source, Path::new(""), &mut diagnostics);
+        assert!(diagnostics.is_empty());
+        assert_eq!(expansion.text, "\n    inc $2\n    inc $2\n");
+        assert_eq!(
+            expansion.origins[1],
+            Origin::Macro {
+                body: Location { file: 0, line: 3 },
+                calls: vec![
+                    ("inner".to_string(), Location { file: 0, line: 6 }),
+                    ("outer".to_string(), Location { file: 0, line: 9 })
+                ]
+            }
+        );
+    }
+
+    #[test]
+    fn test_expand_errors() {
+        let source = "
+.macro rec
+    rec
+.endm
+.macro pair a b
+.endm
+pair $1
+rec
+.endm
+.macro add
+.endm
+.macro open
+";
+        let mut diagnostics = Diagnostics::default();
+        expand(source, Path::new(""), &mut diagnostics);
+        diagnostics.sort();
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| match &d.error {
+                AssemblerError::Macro(e) => (e.clone(), d.span.line, d.notes.len()),
+                e => panic!("unexpected error {}", e),
+            })
+            .collect();
+        assert_eq!(errors.len(), 5);
+        assert!(matches!(
+            errors[0],
+            (MacroError::WrongArgumentCount { found: 1, .. }, 7, 0)
+        ));
+        assert!(matches!(errors[1], (MacroError::Recursion(_), 3, 1)));
+        assert_eq!(errors[2], (MacroError::UnexpectedEnd, 9, 0));
+        assert!(matches!(
+            errors[3],
+            (MacroError::InvalidDefinition(_), 10, 0)
+        ));
+        assert!(matches!(errors[4], (MacroError::Unterminated(_), 12, 0)));
+    }
+
+    #[test]
+    fn test_expand_recursion() {
+        let source = "
+.macro twice r
+    inc \\r
+    twice \\r
+    twice \\r
+.endm
+.macro a
+    b
+.endm
+.macro b
+    a
+.endm
+twice_start: twice $1
+a
+";
+        let mut diagnostics = Diagnostics::default();
+        let expansion = expand(source, Path::new(""), &mut diagnostics);
+        diagnostics.sort();
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.error.to_string(), d.span.line))
+            .collect();
+        assert_eq!(
+            errors,
+            vec![
+                ("Macro twice is expanded recursively".to_string(), 4),
+                ("Macro twice is expanded recursively".to_string(), 5),
+                ("Macro a is expanded recursively".to_string(), 11),
+            ]
+        );
+        assert_eq!(expansion.text, "\ntwice_start:\n    inc $1\n");
+
+        let mut source = String::from(".macro m0\n    nop\n.endm\n");
+        for i in 1..20 {
+            source += &format!(".macro m{}\n    m{}\n    m{}\n.endm\n", i, i - 1, i - 1);
+        }
+        source += "m19\n";
+        let mut diagnostics = Diagnostics::default();
+        expand(&source, Path::new(""), &mut diagnostics);
+        let errors: Vec<_> = diagnostics.iter().map(|d| d.error.to_string()).collect();
+        assert_eq!(errors, vec!["More than 10000 macro calls to expand"]);
+    }
+
+    #[test]
+    fn test_expand_include() {
+        let dir = std::env::temp_dir().join(format!("iridium-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(
+            dir.join("lib/util.iasm"),
+            ".include \"consts.iasm\"\n.macro halt\n    hlt\n.endm\n",
+        )
+        .unwrap();
+        fs::write(dir.join("lib/consts.iasm"), ".equ ONE #1\n").unwrap();
+        fs::write(dir.join("self.iasm"), ".include \"self.iasm\"\n").unwrap();
+
+        let source = ".include \"lib/util.iasm\" ; helpers\nload $0 #ONE\nhalt\n";
+        let mut diagnostics = Diagnostics::default();
+        let expansion = expand(source, &dir, &mut diagnostics);
+        assert!(diagnostics.is_empty(), "{}", diagnostics);
+        assert_eq!(expansion.text, ".equ ONE #1\nload $0 #ONE\n    hlt\n");
+        assert_eq!(
+            expansion.origins[0],
+            Origin::Source(Location { file: 2, line: 1 })
+        );
+
+        let source = ".include \"missing.iasm\"\n.include lib\n.include \"self.iasm\"\n";
+        expand(source, &dir, &mut diagnostics);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.error.to_string(), d.file.clone(), d.span.line))
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(errors.len(), 3);
+        assert!(errors[0].0.starts_with("Unable to include missing.iasm"));
+        assert_eq!(errors[1].0, "Unable to include lib: expected a quoted path");
+        assert_eq!(
+            errors[2].0,
+            "Unable to include self.iasm: file includes itself"
+        );
+        assert!(errors[2].1.as_deref().unwrap().ends_with("self.iasm"));
+    }
+
+    #[test]
+    fn test_include_cycles() {
+        let dir = std::env::temp_dir().join(format!("iridium-cycles-{}", std::process::id()));
+        fs::create_dir_all(dir.join("x")).unwrap();
+        fs::write(dir.join("alias.iasm"), ".include \"x/../alias.iasm\"\n").unwrap();
+        fs::write(dir.join("a.iasm"), ".include \"./b.iasm\"\n").unwrap();
+        fs::write(dir.join("b.iasm"), "nop\n.include \"a.iasm\"\n").unwrap();
+        for i in 0..=MAX_INCLUDE_DEPTH {
+            let text = format!(".include \"deep{}.iasm\"\n", i + 1);
+            fs::write(dir.join(format!("deep{}.iasm", i)), text).unwrap();
+        }
+
+        let source = ".include \"alias.iasm\"\n.include \"a.iasm\"\n.include \"deep0.iasm\"\n";
+        let mut diagnostics = Diagnostics::default();
+        let expansion = expand(source, &dir, &mut diagnostics);
+        let errors: Vec<_> = diagnostics
+            .iter()
+            .map(|d| (d.error.to_string(), d.file.clone().unwrap_or_default()))
+            .collect();
+        let canonical = fs::canonicalize(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(expansion.text, "nop\n");
+        let file = |name: &str| canonical.join(name).to_string_lossy().into_owned();
+        assert_eq!(
+            errors,
+            vec![
+                (
+                    "Unable to include x/../alias.iasm: file includes itself".to_string(),
+                    file("alias.iasm")
+                ),
+                (
+                    "Unable to include a.iasm: file includes itself".to_string(),
+                    file("b.iasm")
+                ),
+                (
+                    format!(
+                        "Unable to include deep{}.iasm: includes are nested too deeply",
+                        MAX_INCLUDE_DEPTH
+                    ),
+                    file(&format!("deep{}.iasm", MAX_INCLUDE_DEPTH - 1))
+                ),
+            ]
+        );
+    }
+}
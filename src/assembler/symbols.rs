@@ -0,0 +1,126 @@
+use crate::bytecode::{SectionKind, Symbol};
+use std::collections::{HashMap, HashSet};
+
+/// Maps label names to their sections and byte offsets,
+/// and constant names to their values.
+///
+/// Imported labels are defined by other objects, their value
+/// is only known after linking and is taken as zero.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SymbolTable {
+    symbols: HashMap<String, (SectionKind, u32)>,
+    constants: HashMap<String, i64>,
+    imports: HashSet<String>,
+}
+
+impl SymbolTable {
+    /// Creates an empty symbol table.
+    pub fn new() -> SymbolTable {
+        SymbolTable::default()
+    }
+
+    /// Adds a new symbol, returns `false` if it is already defined.
+    pub fn add_symbol(&mut self, name: &str, section: SectionKind, offset: u32) -> bool {
+        if self.is_defined(name) {
+            return false;
+        }
+        self.symbols.insert(name.to_string(), (section, offset));
+        true
+    }
+
+    /// Adds a new constant, returns `false` if the name is already defined.
+    pub fn add_constant(&mut self, name: &str, value: i64) -> bool {
+        if self.is_defined(name) {
+            return false;
+        }
+        self.constants.insert(name.to_string(), value);
+        true
+    }
+
+    /// Adds an imported label, returns `false` if the name is already defined.
+    pub fn add_import(&mut self, name: &str) -> bool {
+        if self.is_defined(name) {
+            return false;
+        }
+        self.imports.insert(name.to_string());
+        true
+    }
+
+    pub fn is_import(&self, name: &str) -> bool {
+        self.imports.contains(name)
+    }
+
+    /// Returns a section of the given label.
+    pub fn section(&self, name: &str) -> Option<SectionKind> {
+        self.symbols.get(name).map(|(section, _)| *section)
+    }
+
+    /// Returns an offset of the given symbol within its section.
+    pub fn symbol_value(&self, name: &str) -> Option<u32> {
+        self.symbols.get(name).map(|(_, offset)| *offset)
+    }
+
+    /// Returns a value of the given constant, or an offset of the given label.
+    pub fn value(&self, name: &str) -> Option<i64> {
+        self.constants
+            .get(name)
+            .copied()
+            .or_else(|| self.symbol_value(name).map(i64::from))
+            .or_else(|| self.imports.get(name).map(|_| 0))
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.symbols.contains_key(name)
+            || self.constants.contains_key(name)
+            || self.imports.contains(name)
+    }
+
+    /// Returns all labels ordered by their sections and offsets.
+    pub fn to_vec(&self) -> Vec<Symbol> {
+        let mut symbols: Vec<_> = self
+            .symbols
+            .iter()
+            .map(|(name, (section, offset))| Symbol {
+                name: name.clone(),
+                section: *section,
+                offset: *offset,
+            })
+            .collect();
+        symbols.sort_by_key(|s| (u8::from(s.section), s.offset, s.name.clone()));
+        symbols
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbol_table() {
+        let mut table = SymbolTable::new();
+        assert!(table.add_symbol("test", SectionKind::Code, 12));
+        assert!(!table.add_symbol("test", SectionKind::Code, 16));
+        assert_eq!(table.symbol_value("test"), Some(12));
+        assert_eq!(table.symbol_value("missing"), None);
+        table.add_symbol("first", SectionKind::Code, 0);
+        table.add_symbol("hello", SectionKind::ReadOnlyData, 0);
+        let names: Vec<_> = table.to_vec().into_iter().map(|s| s.name).collect();
+        assert_eq!(names, vec!["first", "test", "hello"]);
+
+        assert!(table.add_constant("SIZE", -4));
+        assert!(!table.add_constant("test", 1));
+        assert!(!table.add_symbol("SIZE", SectionKind::Code, 0));
+        assert_eq!(table.value("SIZE"), Some(-4));
+        assert_eq!(table.value("test"), Some(12));
+        assert_eq!(table.symbol_value("SIZE"), None);
+        assert_eq!(table.to_vec().len(), 3);
+
+        assert!(table.add_import("print"));
+        assert!(!table.add_import("test"));
+        assert!(!table.add_constant("print", 1));
+        assert!(table.is_import("print"));
+        assert_eq!(table.value("print"), Some(0));
+        assert_eq!(table.section("test"), Some(SectionKind::Code));
+        assert_eq!(table.section("print"), None);
+    }
+}
@@ -1,16 +1,38 @@
-use crate::instruction::Opcode;
+use crate::assembler::{expression::Expr, symbols::SymbolTable};
+use crate::bytecode::RelocationTarget;
+use crate::instruction::{Opcode, OperandKind};
 use std::fmt::{self, Display};
 
 #[derive(Debug, Clone)]
 pub enum TokenError {
-    UnexpectedOpcode(Token),
+    UnexpectedOperand(Token, OperandKind),
+    UndefinedLabel(String),
+    /// Value does not fit into an operand of the given kind.
+    OutOfRange(i64, OperandKind),
+    /// Expression overflows or divides by zero.
+    InvalidExpression(String),
+    /// Value of the expression in an object depends on labels
+    /// in a way the linker can't adjust.
+    NotRelocatable(String),
 }
 
 impl Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
-            TokenError::UnexpectedOpcode(token) => {
-                write!(f, "Unexpected opcode found in operand field: {}", token)
+            TokenError::UnexpectedOperand(token, kind) => {
+                write!(f, "Expected {} operand, found: {}", kind, token)
+            }
+            TokenError::UndefinedLabel(name) => {
+                write!(f, "Undefined label: {}", name)
+            }
+            TokenError::InvalidExpression(expr) => {
+                write!(f, "Unable to evaluate: {}", expr)
+            }
+            TokenError::NotRelocatable(expr) => {
+                write!(f, "Unable to relocate: {}", expr)
+            }
+            TokenError::OutOfRange(value, kind) => {
+                write!(f, "Value {} does not fit into {} operand", value, kind)
             }
         }
     }
@@ -18,12 +40,37 @@ impl Display for TokenError {
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Op { code: Opcode },
-    Register { reg_num: u8 },
-    Number { value: i32 },
-    LabelDecl { name: String },
-    LabelUsage { name: String },
-    Directive { name: String },
+    Op {
+        code: Opcode,
+    },
+    Register {
+        reg_num: u8,
+    },
+    Number {
+        value: i32,
+    },
+    Float {
+        value: f64,
+    },
+    LabelDecl {
+        name: String,
+    },
+    LabelUsage {
+        name: String,
+    },
+    /// Name declared by a directive, such as `.equ`.
+    Symbol {
+        name: String,
+    },
+    Expression {
+        expr: Expr,
+    },
+    Directive {
+        name: String,
+    },
+    IrString {
+        value: String,
+    },
     Comment,
 }
 
@@ -33,32 +80,103 @@ impl Display for Token {
             Token::Op { code } => write!(f, "{:?}", code),
             Token::Register { reg_num } => write!(f, "${0}", reg_num),
             Token::Number { value } => write!(f, "{}", value),
+            Token::Float { value } => write!(f, "{:?}", value),
             Token::LabelDecl { name } => write!(f, ":{}", name),
             Token::LabelUsage { name } => write!(f, "@{}", name),
+            Token::Symbol { name } => write!(f, "{}", name),
+            Token::Expression { expr } => write!(f, "{}", expr),
             Token::Directive { name } => write!(f, ".{}", name),
+            Token::IrString { value } => write!(f, "\"{}\"", value.escape_debug()),
             Token::Comment => write!(f, ""),
         }
     }
 }
 
 impl Token {
-    pub fn operand_bytes(&self) -> Result<Vec<u8>, TokenError> {
-        let mut bytes = vec![];
+    /// Checks whether the token can be encoded as an operand of the given kind.
+    pub fn matches(&self, kind: OperandKind) -> bool {
         match self {
-            Token::Register { reg_num } => {
-                bytes.push(*reg_num);
+            Token::Register { .. } => {
+                matches!(kind, OperandKind::Register | OperandKind::FloatRegister)
             }
-            Token::Number { value } => {
-                let converted: u16 = *value as u16;
-                let hi = converted;
-                let lo = hi >> 8;
-                bytes.push(lo as u8);
-                bytes.push(hi as u8);
+            Token::Number { .. } | Token::Expression { .. } => {
+                kind != OperandKind::Register && kind != OperandKind::FloatRegister
             }
-            token => {
-                return Err(TokenError::UnexpectedOpcode(token.clone()));
+            Token::LabelUsage { .. } => kind.range().is_some(),
+            Token::Float { .. } => kind == OperandKind::Float,
+            _ => false,
+        }
+    }
+
+    /// Evaluates an integer token: a number, a label or an expression.
+    pub fn evaluate(&self, symbols: &SymbolTable) -> Option<Result<i64, TokenError>> {
+        match self {
+            Token::Number { value } => Some(Ok(*value as i64)),
+            Token::LabelUsage { name } => Some(
+                symbols
+                    .value(name)
+                    .ok_or_else(|| TokenError::UndefinedLabel(name.clone())),
+            ),
+            Token::Expression { expr } => Some(expr.evaluate(symbols)),
+            _ => None,
+        }
+    }
+
+    /// Finds out which address an integer token depends on
+    /// once the object is linked, see `Expr::relocation`.
+    pub fn relocation(
+        &self,
+        symbols: &SymbolTable,
+    ) -> Result<Option<RelocationTarget>, TokenError> {
+        match self {
+            Token::LabelUsage { name } => Expr::Symbol(name.clone()).relocation(symbols),
+            Token::Expression { expr } => expr.relocation(symbols),
+            _ => Ok(None),
+        }
+    }
+
+    /// Encodes the token as an operand of the given kind.
+    ///
+    /// Integers are stored big-endian in two's complement, so
+    /// an immediate must lie within `kind.range()`: addresses are
+    /// unsigned, other immediates are signed.
+    pub fn operand_bytes(
+        &self,
+        kind: OperandKind,
+        symbols: &SymbolTable,
+    ) -> Result<Vec<u8>, TokenError> {
+        if !self.matches(kind) {
+            return Err(TokenError::UnexpectedOperand(self.clone(), kind));
+        }
+        let mut bytes = vec![];
+        match (self, kind) {
+            (Token::Register { reg_num }, _) => bytes.push(*reg_num),
+            (Token::Float { value }, _) => bytes.extend_from_slice(&value.to_be_bytes()),
+            (token, _) => {
+                let value = match token.evaluate(symbols) {
+                    Some(value) => value?,
+                    None => return Err(TokenError::UnexpectedOperand(token.clone(), kind)),
+                };
+                if kind == OperandKind::Float {
+                    bytes.extend_from_slice(&(value as f64).to_be_bytes());
+                } else {
+                    push_integer(&mut bytes, value, kind)?;
+                }
             }
         }
+        debug_assert_eq!(bytes.len(), kind.size());
         Ok(bytes)
     }
 }
+
+/// Pushes an integer operand, checking that it fits into the operand kind.
+fn push_integer(bytes: &mut Vec<u8>, value: i64, kind: OperandKind) -> Result<(), TokenError> {
+    match kind.range() {
+        Some((min, max)) if (min..=max).contains(&value) => {
+            let be = value.to_be_bytes();
+            bytes.extend_from_slice(&be[be.len() - kind.size()..]);
+            Ok(())
+        }
+        _ => Err(TokenError::OutOfRange(value, kind)),
+    }
+}
@@ -0,0 +1,719 @@
+//! Executable bytecode file format.
+//!
+//! All multi-byte values are big-endian.
+//!
+//! ```text
+//! header:   magic "IRID" | version: u16 | entry point: u32 | section count: u16
+//! sections: kind: u8 | offset: u32 | length: u32   (offsets are from the file start)
+//! ```
+//!
+//! The symbol section is a `u32` count followed by entries of
+//! name length `u16`, UTF-8 name bytes, section kind `u8` and offset `u32`.
+//!
+//! Object files share the layout, but start with the `IROB` magic and
+//! may also contain the export and import sections, a `u32` count of
+//! length-prefixed names each, and the relocation section:
+//!
+//! ```text
+//! count: u32
+//! entry: section: u8 | offset: u32 | operand kind: u8 | target
+//! target: 1 | section: u8    or    2 | name length: u16 | name
+//! ```
+//!
+//! Executables may carry the optional debug section, mapping code
+//! offsets to source locations:
+//!
+//! ```text
+//! files: count: u32 | length-prefixed paths
+//! lines: count: u32 | entry: offset: u32 | file: u32 | line: u32 | column: u32
+//! ```
+use crate::instruction::OperandKind;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+/// Magic number every bytecode file starts with.
+pub const MAGIC: [u8; 4] = *b"IRID";
+
+/// Magic number every object file starts with.
+pub const OBJECT_MAGIC: [u8; 4] = *b"IROB";
+
+/// Version of the file format produced by this build.
+///
+/// Version 2 added the debug section, files of version 1
+/// are still accepted since they are a subset of it.
+pub const FORMAT_VERSION: u16 = 2;
+
+const HEADER_LEN: usize = 12;
+const SECTION_ENTRY_LEN: usize = 9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    BadMagic,
+    UnsupportedVersion(u16),
+    Truncated,
+    UnknownSection(u8),
+    DuplicateSection(SectionKind),
+    SectionOutOfBounds(SectionKind),
+    MissingCode,
+    EntryPointOutOfBounds(u32),
+    InvalidSymbol,
+    InvalidRelocation,
+    InvalidDebugInfo,
+    /// Object file was given where an executable is expected.
+    NotLinked,
+}
+
+impl Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::BadMagic => write!(f, "Not an iridium bytecode file"),
+            BytecodeError::UnsupportedVersion(v) => {
+                write!(f, "Unsupported bytecode format version: {}", v)
+            }
+            BytecodeError::Truncated => write!(f, "Bytecode file is truncated"),
+            BytecodeError::UnknownSection(kind) => write!(f, "Unknown section kind: {}", kind),
+            BytecodeError::DuplicateSection(kind) => {
+                write!(f, "Section appears more than once: {:?}", kind)
+            }
+            BytecodeError::SectionOutOfBounds(kind) => {
+                write!(f, "Section lies outside of the file: {:?}", kind)
+            }
+            BytecodeError::MissingCode => write!(f, "Code section is missing"),
+            BytecodeError::EntryPointOutOfBounds(entry) => {
+                write!(f, "Entry point {} lies outside of the code section", entry)
+            }
+            BytecodeError::InvalidSymbol => write!(f, "Malformed symbol section"),
+            BytecodeError::InvalidRelocation => write!(f, "Malformed relocation section"),
+            BytecodeError::InvalidDebugInfo => write!(f, "Malformed debug section"),
+            BytecodeError::NotLinked => {
+                write!(f, "Object files have to be linked before running")
+            }
+        }
+    }
+}
+
+impl Error for BytecodeError {}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SectionKind {
+    Code,
+    ReadOnlyData,
+    Symbols,
+    /// Object files only: names of the symbols visible to other objects.
+    Exports,
+    /// Object files only: names of the symbols defined by other objects.
+    Imports,
+    /// Object files only: places to adjust when linking.
+    Relocations,
+    /// Source locations of the instructions.
+    Debug,
+}
+
+impl SectionKind {
+    fn from_byte(byte: u8) -> Result<SectionKind, BytecodeError> {
+        match byte {
+            1 => Ok(SectionKind::Code),
+            2 => Ok(SectionKind::ReadOnlyData),
+            3 => Ok(SectionKind::Symbols),
+            4 => Ok(SectionKind::Exports),
+            5 => Ok(SectionKind::Imports),
+            6 => Ok(SectionKind::Relocations),
+            7 => Ok(SectionKind::Debug),
+            kind => Err(BytecodeError::UnknownSection(kind)),
+        }
+    }
+}
+
+impl From<SectionKind> for u8 {
+    fn from(kind: SectionKind) -> Self {
+        match kind {
+            SectionKind::Code => 1,
+            SectionKind::ReadOnlyData => 2,
+            SectionKind::Symbols => 3,
+            SectionKind::Exports => 4,
+            SectionKind::Imports => 5,
+            SectionKind::Relocations => 6,
+            SectionKind::Debug => 7,
+        }
+    }
+}
+
+/// Named offset within a section.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub section: SectionKind,
+    pub offset: u32,
+}
+
+/// Source locations of the instructions, for debuggers.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct DebugInfo {
+    /// Paths of the source files, the main source comes first
+    /// with an empty path unless it's been named.
+    pub files: Vec<String>,
+    /// Locations of the instructions, sorted by their offsets.
+    pub lines: Vec<LineEntry>,
+}
+
+/// Location of the instruction at an offset of the code section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LineEntry {
+    pub offset: u32,
+    /// Index of the file in `DebugInfo::files`.
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+impl DebugInfo {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    /// Names the main source, which has no path otherwise.
+    pub fn set_main_file(&mut self, path: &str) {
+        match self.files.first_mut() {
+            Some(main) => *main = path.to_string(),
+            None => self.files.push(path.to_string()),
+        }
+    }
+
+    /// Formats the source location of the instruction starting
+    /// at the offset as `file:line:column`.
+    pub fn format_location(&self, offset: u32) -> Option<String> {
+        let entry = self.location(offset)?;
+        let file = match self.files.get(entry.file as usize).map(String::as_str) {
+            None | Some("") => "<input>",
+            Some(file) => file,
+        };
+        Some(format!("{}:{}:{}", file, entry.line, entry.column))
+    }
+
+    /// Returns the location of the instruction starting at the offset.
+    pub fn location(&self, offset: u32) -> Option<&LineEntry> {
+        let i = self
+            .lines
+            .binary_search_by_key(&offset, |entry| entry.offset)
+            .ok()?;
+        Some(&self.lines[i])
+    }
+
+    /// Returns the first instruction on the line of the file,
+    /// or on the closest line below it having instructions.
+    pub fn line_start(&self, file: u32, line: u32) -> Option<&LineEntry> {
+        self.lines
+            .iter()
+            .filter(|entry| entry.file == file && entry.line >= line)
+            .min_by_key(|entry| (entry.line, entry.offset))
+    }
+}
+
+/// Assembled program ready to be shipped and loaded into the VM.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Executable {
+    /// Offset of the first instruction to execute within `code`.
+    pub entry_point: u32,
+    pub code: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    pub symbols: Vec<Symbol>,
+    /// Source locations, empty if the executable has none.
+    pub debug_info: DebugInfo,
+}
+
+impl Executable {
+    /// Wraps raw code into an executable starting at its first byte.
+    pub fn from_code(code: Vec<u8>) -> Executable {
+        Executable {
+            code,
+            ..Executable::default()
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = vec![(SectionKind::Code, self.code.clone())];
+        if !self.ro_data.is_empty() {
+            sections.push((SectionKind::ReadOnlyData, self.ro_data.clone()));
+        }
+        if !self.symbols.is_empty() {
+            sections.push((SectionKind::Symbols, symbols_bytes(&self.symbols)));
+        }
+        if !self.debug_info.is_empty() {
+            sections.push((SectionKind::Debug, debug_info_bytes(&self.debug_info)));
+        }
+        write_file(MAGIC, self.entry_point, sections)
+    }
+
+    /// Parses and validates a bytecode file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Executable, BytecodeError> {
+        if bytes.starts_with(&OBJECT_MAGIC) {
+            return Err(BytecodeError::NotLinked);
+        }
+        let (entry_point, sections) = read_file(MAGIC, bytes)?;
+        let mut executable = Executable {
+            entry_point,
+            ..Executable::default()
+        };
+        for (kind, data) in sections {
+            match kind {
+                SectionKind::Code => executable.code = data.to_vec(),
+                SectionKind::ReadOnlyData => executable.ro_data = data.to_vec(),
+                SectionKind::Symbols => executable.symbols = parse_symbols(data)?,
+                SectionKind::Debug => executable.debug_info = parse_debug_info(data)?,
+                kind => return Err(BytecodeError::UnknownSection(kind.into())),
+            }
+        }
+        if entry_point as usize > executable.code.len() {
+            return Err(BytecodeError::EntryPointOutOfBounds(entry_point));
+        }
+        Ok(executable)
+    }
+}
+
+/// Relocatable output of the assembler, linked with other
+/// objects into an executable.
+///
+/// Offsets of the symbols and relocations are relative
+/// to the object's own sections.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Object {
+    pub code: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    /// All the labels declared in the object.
+    pub symbols: Vec<Symbol>,
+    /// Names of the labels visible to other objects.
+    pub exports: Vec<String>,
+    /// Names of the labels other objects have to export.
+    pub imports: Vec<String>,
+    pub relocations: Vec<Relocation>,
+    /// Source locations, empty if the object has none.
+    pub debug_info: DebugInfo,
+}
+
+/// Operand the linker adds an address to, once it places the sections.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relocation {
+    /// Section holding the operand, either code or read-only data.
+    pub section: SectionKind,
+    pub offset: u32,
+    /// Kind of the operand, defines its size and range.
+    pub kind: OperandKind,
+    pub target: RelocationTarget,
+}
+
+/// Address a relocated operand is adjusted by.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelocationTarget {
+    /// Start of the object's own section in the executable.
+    Section(SectionKind),
+    /// Imported symbol.
+    Symbol(String),
+}
+
+impl Object {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut sections = vec![
+            (SectionKind::Code, self.code.clone()),
+            (SectionKind::ReadOnlyData, self.ro_data.clone()),
+            (SectionKind::Symbols, symbols_bytes(&self.symbols)),
+            (SectionKind::Exports, names_bytes(&self.exports)),
+            (SectionKind::Imports, names_bytes(&self.imports)),
+            (SectionKind::Relocations, self.relocations_bytes()),
+        ];
+        if !self.debug_info.is_empty() {
+            sections.push((SectionKind::Debug, debug_info_bytes(&self.debug_info)));
+        }
+        write_file(OBJECT_MAGIC, 0, sections)
+    }
+
+    /// Parses and validates an object file.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Object, BytecodeError> {
+        let (_, sections) = read_file(OBJECT_MAGIC, bytes)?;
+        let mut object = Object::default();
+        for (kind, data) in sections {
+            match kind {
+                SectionKind::Code => object.code = data.to_vec(),
+                SectionKind::ReadOnlyData => object.ro_data = data.to_vec(),
+                SectionKind::Symbols => object.symbols = parse_symbols(data)?,
+                SectionKind::Exports => object.exports = parse_names(data)?,
+                SectionKind::Imports => object.imports = parse_names(data)?,
+                SectionKind::Relocations => object.relocations = parse_relocations(data)?,
+                SectionKind::Debug => object.debug_info = parse_debug_info(data)?,
+            }
+        }
+        Ok(object)
+    }
+
+    fn relocations_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.relocations.len() as u32).to_be_bytes().to_vec();
+        for relocation in &self.relocations {
+            bytes.push(relocation.section.into());
+            bytes.extend_from_slice(&relocation.offset.to_be_bytes());
+            bytes.push(match relocation.kind {
+                OperandKind::Immediate => 1,
+                OperandKind::Address => 2,
+                _ => 3,
+            });
+            match &relocation.target {
+                RelocationTarget::Section(section) => {
+                    bytes.extend_from_slice(&[1, (*section).into()])
+                }
+                RelocationTarget::Symbol(name) => {
+                    bytes.push(2);
+                    push_name(&mut bytes, name);
+                }
+            }
+        }
+        bytes
+    }
+}
+
+/// Section kind along with its contents.
+type Section<'a> = (SectionKind, &'a [u8]);
+
+/// Writes the header, the section table and the sections.
+fn write_file(magic: [u8; 4], entry_point: u32, sections: Vec<(SectionKind, Vec<u8>)>) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    bytes.extend_from_slice(&FORMAT_VERSION.to_be_bytes());
+    bytes.extend_from_slice(&entry_point.to_be_bytes());
+    bytes.extend_from_slice(&(sections.len() as u16).to_be_bytes());
+
+    let mut offset = HEADER_LEN + SECTION_ENTRY_LEN * sections.len();
+    for (kind, data) in &sections {
+        bytes.push((*kind).into());
+        bytes.extend_from_slice(&(offset as u32).to_be_bytes());
+        bytes.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        offset += data.len();
+    }
+    for (_, data) in sections {
+        bytes.extend(data);
+    }
+    bytes
+}
+
+/// Validates the header and the section table, returns
+/// the entry point along with the sections.
+fn read_file(magic: [u8; 4], bytes: &[u8]) -> Result<(u32, Vec<Section<'_>>), BytecodeError> {
+    let mut reader = Reader::new(bytes);
+    if reader
+        .bytes(magic.len())
+        .map_err(|_| BytecodeError::BadMagic)?
+        != magic
+    {
+        return Err(BytecodeError::BadMagic);
+    }
+    let version = reader.u16()?;
+    if !(1..=FORMAT_VERSION).contains(&version) {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let entry_point = reader.u32()?;
+    let count = reader.u16()?;
+
+    let mut sections: Vec<Section> = vec![];
+    for _ in 0..count {
+        let kind = SectionKind::from_byte(reader.u8()?)?;
+        let offset = reader.u32()? as usize;
+        let len = reader.u32()? as usize;
+        if sections.iter().any(|(seen, _)| *seen == kind) {
+            return Err(BytecodeError::DuplicateSection(kind));
+        }
+        let data = offset
+            .checked_add(len)
+            .and_then(|end| bytes.get(offset..end))
+            .ok_or(BytecodeError::SectionOutOfBounds(kind))?;
+        sections.push((kind, data));
+    }
+    if !sections.iter().any(|(kind, _)| *kind == SectionKind::Code) {
+        return Err(BytecodeError::MissingCode);
+    }
+    Ok((entry_point, sections))
+}
+
+fn push_name(bytes: &mut Vec<u8>, name: &str) {
+    bytes.extend_from_slice(&(name.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(name.as_bytes());
+}
+
+fn symbols_bytes(symbols: &[Symbol]) -> Vec<u8> {
+    let mut bytes = (symbols.len() as u32).to_be_bytes().to_vec();
+    for symbol in symbols {
+        push_name(&mut bytes, &symbol.name);
+        bytes.push(symbol.section.into());
+        bytes.extend_from_slice(&symbol.offset.to_be_bytes());
+    }
+    bytes
+}
+
+fn names_bytes(names: &[String]) -> Vec<u8> {
+    let mut bytes = (names.len() as u32).to_be_bytes().to_vec();
+    for name in names {
+        push_name(&mut bytes, name);
+    }
+    bytes
+}
+
+fn debug_info_bytes(debug_info: &DebugInfo) -> Vec<u8> {
+    let mut bytes = names_bytes(&debug_info.files);
+    bytes.extend_from_slice(&(debug_info.lines.len() as u32).to_be_bytes());
+    for entry in &debug_info.lines {
+        for value in &[entry.offset, entry.file, entry.line, entry.column] {
+            bytes.extend_from_slice(&value.to_be_bytes());
+        }
+    }
+    bytes
+}
+
+fn parse_symbols(data: &[u8]) -> Result<Vec<Symbol>, BytecodeError> {
+    let mut reader = Reader::new(data);
+    let count = reader.u32().map_err(|_| BytecodeError::InvalidSymbol)?;
+    let mut symbols = vec![];
+    for _ in 0..count {
+        let symbol = reader
+            .name()
+            .and_then(|name| Ok((name, reader.u8()?, reader.u32()?)));
+        let (name, section, offset) = symbol.map_err(|_| BytecodeError::InvalidSymbol)?;
+        let section = SectionKind::from_byte(section).map_err(|_| BytecodeError::InvalidSymbol)?;
+        symbols.push(Symbol {
+            name,
+            section,
+            offset,
+        });
+    }
+    Ok(symbols)
+}
+
+fn parse_names(data: &[u8]) -> Result<Vec<String>, BytecodeError> {
+    let mut reader = Reader::new(data);
+    let count = reader.u32().map_err(|_| BytecodeError::InvalidSymbol)?;
+    (0..count)
+        .map(|_| reader.name().map_err(|_| BytecodeError::InvalidSymbol))
+        .collect()
+}
+
+fn parse_relocations(data: &[u8]) -> Result<Vec<Relocation>, BytecodeError> {
+    let mut reader = Reader::new(data);
+    let count = reader.u32().map_err(|_| BytecodeError::InvalidRelocation)?;
+    (0..count)
+        .map(|_| {
+            reader
+                .relocation()
+                .map_err(|_| BytecodeError::InvalidRelocation)
+        })
+        .collect()
+}
+
+fn parse_debug_info(data: &[u8]) -> Result<DebugInfo, BytecodeError> {
+    let mut reader = Reader::new(data);
+    let mut read = || -> Result<DebugInfo, BytecodeError> {
+        let files = (0..reader.u32()?)
+            .map(|_| reader.name())
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut lines = vec![];
+        for _ in 0..reader.u32()? {
+            let entry = LineEntry {
+                offset: reader.u32()?,
+                file: reader.u32()?,
+                line: reader.u32()?,
+                column: reader.u32()?,
+            };
+            if entry.file as usize >= files.len()
+                || lines
+                    .last()
+                    .is_some_and(|last: &LineEntry| last.offset >= entry.offset)
+            {
+                return Err(BytecodeError::InvalidDebugInfo);
+            }
+            lines.push(entry);
+        }
+        Ok(DebugInfo { files, lines })
+    };
+    read().map_err(|_| BytecodeError::InvalidDebugInfo)
+}
+
+/// Reads big-endian values from a byte slice.
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Reader<'a> {
+        Reader { bytes, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], BytecodeError> {
+        let end = self.pos.checked_add(n).ok_or(BytecodeError::Truncated)?;
+        let result = self
+            .bytes
+            .get(self.pos..end)
+            .ok_or(BytecodeError::Truncated)?;
+        self.pos = end;
+        Ok(result)
+    }
+
+    fn u8(&mut self) -> Result<u8, BytecodeError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn relocation(&mut self) -> Result<Relocation, BytecodeError> {
+        let section = SectionKind::from_byte(self.u8()?)?;
+        let offset = self.u32()?;
+        let kind = match self.u8()? {
+            1 => OperandKind::Immediate,
+            2 => OperandKind::Address,
+            3 => OperandKind::Word,
+            _ => return Err(BytecodeError::InvalidRelocation),
+        };
+        let target = match self.u8()? {
+            1 => RelocationTarget::Section(SectionKind::from_byte(self.u8()?)?),
+            2 => RelocationTarget::Symbol(self.name()?),
+            _ => return Err(BytecodeError::InvalidRelocation),
+        };
+        Ok(Relocation {
+            section,
+            offset,
+            kind,
+            target,
+        })
+    }
+
+    /// Reads a UTF-8 string prefixed with its `u16` length.
+    fn name(&mut self) -> Result<String, BytecodeError> {
+        let len = self.u16()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).map_err(|_| BytecodeError::InvalidSymbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn executable() -> Executable {
+        Executable {
+            entry_point: 2,
+            code: vec![0, 0, 99],
+            ro_data: vec![1, 2, 3],
+            symbols: vec![Symbol {
+                name: "main".to_string(),
+                section: SectionKind::Code,
+                offset: 2,
+            }],
+            debug_info: DebugInfo {
+                files: vec!["main.iasm".to_string()],
+                lines: vec![LineEntry {
+                    offset: 2,
+                    file: 0,
+                    line: 3,
+                    column: 5,
+                }],
+            },
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let bytes = executable().to_bytes();
+        assert_eq!(&bytes[..4], b"IRID");
+        assert_eq!(Executable::from_bytes(&bytes), Ok(executable()));
+
+        let code_only = Executable::from_code(vec![99]);
+        assert_eq!(Executable::from_bytes(&code_only.to_bytes()), Ok(code_only));
+    }
+
+    #[test]
+    fn test_debug_info() {
+        let debug_info = executable().debug_info;
+        assert_eq!(debug_info.format_location(2).unwrap(), "main.iasm:3:5");
+        assert_eq!(debug_info.format_location(1), None);
+        let mut unnamed = DebugInfo {
+            files: vec![String::new()],
+            ..debug_info
+        };
+        assert_eq!(unnamed.format_location(2).unwrap(), "<input>:3:5");
+        unnamed.set_main_file("prog.iasm");
+        assert_eq!(unnamed.files, vec!["prog.iasm"]);
+    }
+
+    #[test]
+    fn test_object_roundtrip() {
+        let object = Object {
+            code: vec![1, 2],
+            ro_data: vec![3],
+            symbols: executable().symbols,
+            exports: vec!["main".to_string()],
+            imports: vec!["print".to_string()],
+            relocations: vec![
+                Relocation {
+                    section: SectionKind::Code,
+                    offset: 1,
+                    kind: OperandKind::Address,
+                    target: RelocationTarget::Symbol("print".to_string()),
+                },
+                Relocation {
+                    section: SectionKind::ReadOnlyData,
+                    offset: 0,
+                    kind: OperandKind::Word,
+                    target: RelocationTarget::Section(SectionKind::Code),
+                },
+            ],
+            debug_info: executable().debug_info,
+        };
+        let bytes = object.to_bytes();
+        assert_eq!(&bytes[..4], b"IROB");
+        assert_eq!(Object::from_bytes(&bytes), Ok(object));
+        assert_eq!(
+            Executable::from_bytes(&bytes),
+            Err(BytecodeError::NotLinked)
+        );
+        assert_eq!(
+            Object::from_bytes(&executable().to_bytes()),
+            Err(BytecodeError::BadMagic)
+        );
+    }
+
+    #[test]
+    fn test_validation() {
+        assert_eq!(Executable::from_bytes(&[99]), Err(BytecodeError::BadMagic));
+
+        let mut bytes = executable().to_bytes();
+        bytes[5] = 9;
+        assert_eq!(
+            Executable::from_bytes(&bytes),
+            Err(BytecodeError::UnsupportedVersion(9))
+        );
+
+        let mut version_1 = Executable::from_code(vec![99]).to_bytes();
+        version_1[5] = 1;
+        assert!(Executable::from_bytes(&version_1).is_ok());
+
+        let bytes = executable().to_bytes();
+        assert_eq!(
+            Executable::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::SectionOutOfBounds(SectionKind::Debug))
+        );
+
+        let mut bad_file = executable();
+        bad_file.debug_info.lines[0].file = 1;
+        assert_eq!(
+            Executable::from_bytes(&bad_file.to_bytes()),
+            Err(BytecodeError::InvalidDebugInfo)
+        );
+
+        let mut bad_entry = executable();
+        bad_entry.entry_point = 10;
+        assert_eq!(
+            Executable::from_bytes(&bad_entry.to_bytes()),
+            Err(BytecodeError::EntryPointOutOfBounds(10))
+        );
+    }
+}
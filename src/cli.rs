@@ -0,0 +1,645 @@
+use crate::assembler::{Assembler, Diagnostics};
+use crate::bytecode::{BytecodeError, Executable, Object, MAGIC, OBJECT_MAGIC};
+use crate::dap::Server;
+use crate::disassembler::disassemble;
+use crate::linker::{link, LinkError};
+use crate::repl::REPL;
+use crate::stdlib;
+use crate::vm::{JsonTracer, TextTracer, Tracer, VmError, VM};
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// Extension of the bytecode files written by `asm`.
+pub const BYTECODE_EXTENSION: &str = "ibc";
+
+/// Extension of the object files written by `asm -c`.
+pub const OBJECT_EXTENSION: &str = "iob";
+
+pub const USAGE: &str = "\
+Usage:
+    iridium run <file> [--trace <output>] [--trace-format text|json]
+                                            Assemble (if needed) and run a program,
+                                            tracing executed instructions to
+                                            the output (- for stderr)
+    iridium asm <file> [-c] [-o <output>]   Assemble a source file into bytecode,
+                                            or into an object file with -c
+    iridium link <files>... [--stdlib] [-o <output>]
+                                            Link object files into bytecode,
+                                            adding the stdlib modules they import
+    iridium disasm <file>                   Print assembly of a bytecode file
+    iridium repl                            Start an interactive session
+    iridium dap                             Serve the Debug Adapter Protocol
+                                            over stdin and stdout";
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    /// Runs either a source or a bytecode file.
+    Run {
+        path: PathBuf,
+        trace: Option<Trace>,
+    },
+    Assemble {
+        input: PathBuf,
+        output: PathBuf,
+        /// Produce an object file instead of an executable.
+        object: bool,
+    },
+    Link {
+        inputs: Vec<PathBuf>,
+        output: PathBuf,
+        /// Link the standard library modules the inputs import.
+        stdlib: bool,
+    },
+    Disassemble {
+        path: PathBuf,
+    },
+    Repl,
+    /// Serves a debugger session to an editor.
+    Dap,
+}
+
+/// Where and how `run` records the executed instructions.
+#[derive(Debug, PartialEq)]
+pub struct Trace {
+    /// Trace file, `-` for stderr.
+    pub path: PathBuf,
+    pub format: TraceFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceFormat {
+    Text,
+    /// JSON Lines, an object per instruction.
+    Json,
+}
+
+#[derive(Debug)]
+pub enum CliError {
+    Usage(String),
+    Io {
+        path: PathBuf,
+        error: io::Error,
+    },
+    Assembler(Diagnostics),
+    Bytecode(BytecodeError),
+    Link(Vec<LinkError>),
+    Vm {
+        error: VmError,
+        /// Source location of the faulting instruction, if known.
+        location: Option<String>,
+    },
+    Dap(io::Error),
+}
+
+impl CliError {
+    /// Process exit code to report the error with.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            CliError::Usage(_) => 2,
+            _ => 1,
+        }
+    }
+}
+
+impl Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CliError::Usage(msg) => write!(f, "{}\n\n{}", msg, USAGE),
+            CliError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            CliError::Assembler(diagnostics) => write!(f, "{}", diagnostics),
+            CliError::Bytecode(e) => write!(f, "Invalid bytecode: {}", e),
+            CliError::Link(errors) => {
+                for e in errors {
+                    writeln!(f, "error: {}", e)?;
+                }
+                write!(f, "{} error(s) found", errors.len())
+            }
+            CliError::Vm {
+                error,
+                location: Some(location),
+            } => write!(f, "VM error: {} ({})", error, location),
+            CliError::Vm { error, .. } => write!(f, "VM error: {}", error),
+            CliError::Dap(e) => write!(f, "Debug adapter error: {}", e),
+        }
+    }
+}
+
+impl Error for CliError {}
+
+impl From<BytecodeError> for CliError {
+    fn from(e: BytecodeError) -> Self {
+        CliError::Bytecode(e)
+    }
+}
+
+impl From<VmError> for CliError {
+    fn from(e: VmError) -> Self {
+        CliError::Vm {
+            error: e,
+            location: None,
+        }
+    }
+}
+
+/// Parses command-line arguments, not including the program name.
+///
+/// Starts the REPL when no subcommand is given.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, CliError> {
+    let mut args = args.into_iter();
+    let command = match args.next() {
+        None => return Ok(Command::Repl),
+        Some(command) => command,
+    };
+    let mut positional = vec![];
+    let mut output = None;
+    let mut object = false;
+    let mut stdlib = false;
+    let mut trace = None;
+    let mut trace_format = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" | "--output" if command == "asm" || command == "link" => match args.next() {
+                Some(path) => output = Some(PathBuf::from(path)),
+                None => return Err(CliError::Usage(format!("Missing value for {}", arg))),
+            },
+            "-c" | "--object" if command == "asm" => object = true,
+            "--stdlib" if command == "link" => stdlib = true,
+            "--trace" if command == "run" => match args.next() {
+                Some(path) => trace = Some(PathBuf::from(path)),
+                None => return Err(CliError::Usage(format!("Missing value for {}", arg))),
+            },
+            "--trace-format" if command == "run" => {
+                trace_format = match args.next().as_deref() {
+                    Some("text") => Some(TraceFormat::Text),
+                    Some("json") => Some(TraceFormat::Json),
+                    _ => {
+                        return Err(CliError::Usage(
+                            "Expected text or json trace format".to_string(),
+                        ))
+                    }
+                }
+            }
+            s if s.starts_with('-') => {
+                return Err(CliError::Usage(format!("Unknown option: {}", arg)));
+            }
+            _ => positional.push(PathBuf::from(arg)),
+        }
+    }
+
+    let file = |mut positional: Vec<PathBuf>| match positional.len() {
+        1 => Ok(positional.remove(0)),
+        _ => Err(CliError::Usage(format!(
+            "Expected exactly one file for {}",
+            command
+        ))),
+    };
+    match command.as_str() {
+        "run" => {
+            let trace = match (trace, trace_format) {
+                (Some(path), format) => Some(Trace {
+                    path,
+                    format: format.unwrap_or(TraceFormat::Text),
+                }),
+                (None, Some(_)) => {
+                    return Err(CliError::Usage(
+                        "--trace-format requires --trace".to_string(),
+                    ))
+                }
+                (None, None) => None,
+            };
+            Ok(Command::Run {
+                path: file(positional)?,
+                trace,
+            })
+        }
+        "asm" => {
+            let input = file(positional)?;
+            let extension = if object {
+                OBJECT_EXTENSION
+            } else {
+                BYTECODE_EXTENSION
+            };
+            let output = output.unwrap_or_else(|| input.with_extension(extension));
+            Ok(Command::Assemble {
+                input,
+                output,
+                object,
+            })
+        }
+        "link" if positional.is_empty() => Err(CliError::Usage(
+            "Expected at least one file for link".to_string(),
+        )),
+        "link" => {
+            let output = output.unwrap_or_else(|| positional[0].with_extension(BYTECODE_EXTENSION));
+            Ok(Command::Link {
+                inputs: positional,
+                output,
+                stdlib,
+            })
+        }
+        "disasm" => Ok(Command::Disassemble {
+            path: file(positional)?,
+        }),
+        "repl" if positional.is_empty() => Ok(Command::Repl),
+        "repl" => Err(CliError::Usage("repl takes no arguments".to_string())),
+        "dap" if positional.is_empty() => Ok(Command::Dap),
+        "dap" => Err(CliError::Usage("dap takes no arguments".to_string())),
+        _ => Err(CliError::Usage(format!("Unknown command: {}", command))),
+    }
+}
+
+impl Command {
+    pub fn execute(self) -> Result<(), CliError> {
+        match self {
+            Command::Run { path, trace } => {
+                let mut vm = match trace {
+                    Some(trace) => VM::new().with_tracer(trace.tracer()?),
+                    None => VM::new(),
+                };
+                vm.load_executable(load_executable(&path)?);
+                match vm.run() {
+                    Ok(_) => Ok(()),
+                    Err(error) => Err(CliError::Vm {
+                        location: vm.source_location(error.pc()),
+                        error,
+                    }),
+                }
+            }
+            Command::Assemble {
+                input,
+                output,
+                object,
+            } => {
+                let bytes = match object {
+                    true => assemble_object_file(&input)?.to_bytes(),
+                    false => assemble_file(&input)?.to_bytes(),
+                };
+                write_file(&output, &bytes)
+            }
+            Command::Link {
+                inputs,
+                output,
+                stdlib,
+            } => {
+                let mut objects = vec![];
+                for input in inputs {
+                    objects.push(Object::from_bytes(&read_file(&input)?)?);
+                }
+                if stdlib {
+                    objects.extend(stdlib::required(&objects)?);
+                }
+                let executable = link(&objects).map_err(CliError::Link)?;
+                write_file(&output, &executable.to_bytes())
+            }
+            Command::Disassemble { path } => {
+                let bytes = read_file(&path)?;
+                print!("{}", disassemble(&Executable::from_bytes(&bytes)?));
+                Ok(())
+            }
+            Command::Repl => REPL::new(VM::new()).run(),
+            Command::Dap => Server::new(BufReader::new(io::stdin()), io::stdout().lock())
+                .run()
+                .map_err(CliError::Dap),
+        }
+    }
+}
+
+impl Trace {
+    /// Creates a tracer writing to the trace file.
+    fn tracer(&self) -> Result<Box<dyn Tracer>, CliError> {
+        let output: Box<dyn Write> = match self.path.to_str() {
+            Some("-") => Box::new(io::stderr()),
+            _ => {
+                let file = File::create(&self.path).map_err(|error| CliError::Io {
+                    path: self.path.clone(),
+                    error,
+                })?;
+                Box::new(BufWriter::new(file))
+            }
+        };
+        Ok(match self.format {
+            TraceFormat::Text => Box::new(TextTracer::new(output)),
+            TraceFormat::Json => Box::new(JsonTracer::new(output)),
+        })
+    }
+}
+
+/// Loads a bytecode file, or assembles a source one.
+pub(crate) fn load_executable(path: &Path) -> Result<Executable, CliError> {
+    let bytes = read_file(path)?;
+    if bytes.starts_with(&MAGIC) || bytes.starts_with(&OBJECT_MAGIC) {
+        Ok(Executable::from_bytes(&bytes)?)
+    } else {
+        assemble_file(path)
+    }
+}
+
+fn assemble_file(path: &Path) -> Result<Executable, CliError> {
+    let source = read_source(path)?;
+    let name = path.to_string_lossy();
+    let mut executable = assembler_for(path)
+        .assemble(&source)
+        .map_err(|diagnostics| CliError::Assembler(diagnostics.with_file(&name)))?;
+    executable.debug_info.set_main_file(&name);
+    Ok(executable)
+}
+
+fn assemble_object_file(path: &Path) -> Result<Object, CliError> {
+    let source = read_source(path)?;
+    let name = path.to_string_lossy();
+    let mut object = assembler_for(path)
+        .assemble_object(&source)
+        .map_err(|diagnostics| CliError::Assembler(diagnostics.with_file(&name)))?;
+    object.debug_info.set_main_file(&name);
+    Ok(object)
+}
+
+/// Creates an assembler resolving includes relative to the source file.
+pub(crate) fn assembler_for(path: &Path) -> Assembler {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Assembler::new().with_base_dir(dir)
+}
+
+fn read_source(path: &Path) -> Result<String, CliError> {
+    fs::read_to_string(path).map_err(|error| CliError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn write_file(path: &Path, bytes: &[u8]) -> Result<(), CliError> {
+    fs::write(path, bytes).map_err(|error| CliError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+fn read_file(path: &Path) -> Result<Vec<u8>, CliError> {
+    fs::read(path).map_err(|error| CliError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Command, CliError> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_parse_args() {
+        assert_eq!(parse(&[]).unwrap(), Command::Repl);
+        assert_eq!(parse(&["repl"]).unwrap(), Command::Repl);
+        assert_eq!(parse(&["dap"]).unwrap(), Command::Dap);
+        assert_eq!(
+            parse(&["run", "a.iasm"]).unwrap(),
+            Command::Run {
+                path: "a.iasm".into(),
+                trace: None
+            }
+        );
+        assert_eq!(
+            parse(&["asm", "a.iasm"]).unwrap(),
+            Command::Assemble {
+                input: "a.iasm".into(),
+                output: "a.ibc".into(),
+                object: false
+            }
+        );
+        assert_eq!(
+            parse(&["asm", "-o", "out.ibc", "a.iasm"]).unwrap(),
+            Command::Assemble {
+                input: "a.iasm".into(),
+                output: "out.ibc".into(),
+                object: false
+            }
+        );
+        assert_eq!(
+            parse(&["asm", "-c", "lib/a.iasm"]).unwrap(),
+            Command::Assemble {
+                input: "lib/a.iasm".into(),
+                output: "lib/a.iob".into(),
+                object: true
+            }
+        );
+        assert_eq!(
+            parse(&["link", "a.iob", "b.iob", "-o", "prog.ibc"]).unwrap(),
+            Command::Link {
+                inputs: vec!["a.iob".into(), "b.iob".into()],
+                output: "prog.ibc".into(),
+                stdlib: false
+            }
+        );
+        assert_eq!(
+            parse(&["link", "--stdlib", "a.iob"]).unwrap(),
+            Command::Link {
+                inputs: vec!["a.iob".into()],
+                output: "a.ibc".into(),
+                stdlib: true
+            }
+        );
+        assert_eq!(
+            parse(&["run", "--trace", "-", "a.iasm"]).unwrap(),
+            Command::Run {
+                path: "a.iasm".into(),
+                trace: Some(Trace {
+                    path: "-".into(),
+                    format: TraceFormat::Text
+                })
+            }
+        );
+        assert_eq!(
+            parse(&[
+                "run",
+                "a.ibc",
+                "--trace-format",
+                "json",
+                "--trace",
+                "t.jsonl"
+            ])
+            .unwrap(),
+            Command::Run {
+                path: "a.ibc".into(),
+                trace: Some(Trace {
+                    path: "t.jsonl".into(),
+                    format: TraceFormat::Json
+                })
+            }
+        );
+        assert_eq!(
+            parse(&["disasm", "a.ibc"]).unwrap(),
+            Command::Disassemble {
+                path: "a.ibc".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_args_errors() {
+        for args in [
+            &["build"][..],
+            &["run"],
+            &["run", "a", "b"],
+            &["run", "-o", "x", "a"],
+            &["asm", "a", "-o"],
+            &["repl", "a"],
+            &["dap", "a"],
+            &["link"],
+            &["run", "-c", "a"],
+            &["asm", "--stdlib", "a"],
+            &["run", "a", "--trace"],
+            &["run", "a", "--trace-format", "json"],
+            &["run", "a", "--trace", "t", "--trace-format", "xml"],
+            &["asm", "a", "--trace", "t"],
+        ] {
+            let error = parse(args).unwrap_err();
+            assert!(matches!(error, CliError::Usage(_)), "{:?}", args);
+            assert_eq!(error.exit_code(), 2);
+        }
+    }
+
+    #[test]
+    fn test_execute() {
+        let dir = std::env::temp_dir().join(format!("iridium-cli-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("prog.iasm");
+        fs::write(&source, "load $0 #1\nhlt\n").unwrap();
+
+        Command::Run {
+            path: source.clone(),
+            trace: None,
+        }
+        .execute()
+        .unwrap();
+        Command::Assemble {
+            input: source.clone(),
+            output: dir.join("prog.ibc"),
+            object: false,
+        }
+        .execute()
+        .unwrap();
+        let bytes = fs::read(dir.join("prog.ibc")).unwrap();
+        let executable = Executable::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            executable.debug_info.format_location(0),
+            Some(format!("{}:1:1", source.display()))
+        );
+        Command::Run {
+            path: dir.join("prog.ibc"),
+            trace: None,
+        }
+        .execute()
+        .unwrap();
+        Command::Run {
+            path: dir.join("prog.ibc"),
+            trace: Some(Trace {
+                path: dir.join("trace.jsonl"),
+                format: TraceFormat::Json,
+            }),
+        }
+        .execute()
+        .unwrap();
+        let trace = fs::read_to_string(dir.join("trace.jsonl")).unwrap();
+        assert_eq!(trace.lines().count(), 2);
+        let first: serde_json::Value = serde_json::from_str(trace.lines().next().unwrap()).unwrap();
+        assert_eq!(first["instruction"], "load $0 #1");
+        let error = Command::Run {
+            path: source.clone(),
+            trace: Some(Trace {
+                path: dir.join("missing").join("trace.txt"),
+                format: TraceFormat::Text,
+            }),
+        }
+        .execute()
+        .unwrap_err();
+        assert!(matches!(error, CliError::Io { .. }));
+
+        fs::write(&source, "daol $0\nload $0 #1\n").unwrap();
+        let error = Command::Run {
+            path: source.clone(),
+            trace: None,
+        }
+        .execute()
+        .unwrap_err();
+        assert!(error.to_string().contains("prog.iasm:1:1"));
+        assert_eq!(error.exit_code(), 1);
+
+        fs::write(&source, "load $0 #1\ndiv $0 $1 $2\n").unwrap();
+        let error = Command::Run {
+            path: source.clone(),
+            trace: None,
+        }
+        .execute()
+        .unwrap_err();
+        assert!(matches!(error, CliError::Vm { .. }));
+        assert!(error.to_string().ends_with("prog.iasm:2:1)"), "{}", error);
+        assert_eq!(error.exit_code(), 1);
+
+        fs::write(dir.join("lib.iasm"), ".export one\none: load $0 #1\nret\n").unwrap();
+        fs::write(&source, ".include \"defs.iasm\"\nmain: call @one\nhlt\n").unwrap();
+        fs::write(dir.join("defs.iasm"), ".import one\n").unwrap();
+        for input in [source.clone(), dir.join("lib.iasm")] {
+            Command::Assemble {
+                output: input.with_extension(OBJECT_EXTENSION),
+                input,
+                object: true,
+            }
+            .execute()
+            .unwrap();
+        }
+        let error = Command::Run {
+            path: dir.join("prog.iob"),
+            trace: None,
+        }
+        .execute()
+        .unwrap_err();
+        assert!(matches!(
+            error,
+            CliError::Bytecode(BytecodeError::NotLinked)
+        ));
+        let link = |inputs: &[&str], stdlib| Command::Link {
+            inputs: inputs.iter().map(|input| dir.join(input)).collect(),
+            output: dir.join("linked.ibc"),
+            stdlib,
+        };
+        link(&["prog.iob", "lib.iob"], false).execute().unwrap();
+        Command::Run {
+            path: dir.join("linked.ibc"),
+            trace: None,
+        }
+        .execute()
+        .unwrap();
+        let error = link(&["prog.iob"], true).execute().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "error: Undefined symbol: one\n1 error(s) found"
+        );
+        fs::write(&source, ".import abs\nload $0 #-1\ncall @abs\nhlt\n").unwrap();
+        Command::Assemble {
+            input: source.clone(),
+            output: dir.join("prog.iob"),
+            object: true,
+        }
+        .execute()
+        .unwrap();
+        assert!(link(&["prog.iob"], false).execute().is_err());
+        link(&["prog.iob"], true).execute().unwrap();
+        let executable = load_executable(&dir.join("linked.ibc")).unwrap();
+        assert_eq!(executable.symbols[0].name, "abs");
+
+        let error = Command::Run {
+            path: dir.join("missing.iasm"),
+            trace: None,
+        }
+        .execute()
+        .unwrap_err();
+        assert!(matches!(error, CliError::Io { .. }));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,774 @@
+//! Debug Adapter Protocol server, to debug programs from editors.
+//!
+//! Messages are exchanged over a pair of streams, normally stdin and
+//! stdout, each one prefixed with a `Content-Length` header.
+//! The program is a single thread with a single stack frame,
+//! registers and heap are shown in the variables view.
+//!
+//! Requests are read by a separate thread, so that a running program
+//! can be paused: the server checks for them between slices of
+//! instructions and handles them once the program stops.
+
+use crate::bytecode::{DebugInfo, Executable, SectionKind, MAGIC};
+use crate::cli::assembler_for;
+use crate::debugger::{Debugger, StopReason};
+use crate::vm::{OutputBuffer, VmError, VM};
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+
+/// Id of the only thread reported to the client.
+const THREAD_ID: i64 = 1;
+
+/// Variable references of the scopes.
+const REGISTERS: i64 = 1;
+const FLOAT_REGISTERS: i64 = 2;
+const HEAP: i64 = 3;
+
+/// Number of heap bytes shown as a single variable.
+const HEAP_ROW: usize = 16;
+
+/// Debug adapter serving a single debugging session.
+pub struct Server<W> {
+    inbox: Rc<RefCell<Inbox>>,
+    output: W,
+    /// Sequence number of the last message sent.
+    seq: i64,
+    session: Option<Session>,
+    /// Events to send after the response to the current request.
+    events: Vec<Value>,
+}
+
+/// Program launched by the client.
+struct Session {
+    debugger: Debugger,
+    output: OutputBuffer,
+    stop_on_entry: bool,
+    /// Breakpoint addresses set in each source file.
+    breakpoints: HashMap<u32, Vec<usize>>,
+    terminated: bool,
+}
+
+/// Requests read from the client.
+struct Inbox {
+    receiver: Receiver<io::Result<Value>>,
+    /// Requests received while the program was running.
+    pending: VecDeque<io::Result<Value>>,
+    closed: bool,
+}
+
+impl Inbox {
+    /// Takes the requests received so far without waiting for more,
+    /// returns true if the client asks to pause or has gone away.
+    fn poll(&mut self) -> bool {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(request) => self.pending.push_back(request),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        let pause = |request: &io::Result<Value>| matches!(request, Ok(request) if request["command"] == "pause");
+        self.closed || self.pending.iter().any(pause)
+    }
+
+    /// Returns the next request, waiting for it if there's none pending.
+    fn next(&mut self) -> Option<io::Result<Value>> {
+        self.pending
+            .pop_front()
+            .or_else(|| self.receiver.recv().ok())
+    }
+}
+
+impl<W: Write> Server<W> {
+    pub fn new<R: BufRead + Send + 'static>(input: R, output: W) -> Server<W> {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = input;
+            while let Some(message) = read_message(&mut input).transpose() {
+                let failed = message.is_err();
+                if sender.send(message).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        let inbox = Inbox {
+            receiver,
+            pending: VecDeque::new(),
+            closed: false,
+        };
+        Server {
+            inbox: Rc::new(RefCell::new(inbox)),
+            output,
+            seq: 0,
+            session: None,
+            events: vec![],
+        }
+    }
+
+    /// Serves requests until the client disconnects or closes the input.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            let request = self.inbox.borrow_mut().next();
+            let request = match request {
+                Some(request) => request?,
+                None => break,
+            };
+            let command = request["command"].as_str().unwrap_or_default().to_string();
+            let result = self.handle(&command, &request["arguments"]);
+            self.respond(&request, result)?;
+            for event in std::mem::take(&mut self.events) {
+                self.send(event)?;
+            }
+            if command == "disconnect" {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request, returning the body of the response.
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value, String> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsSteppingGranularity": true,
+            })),
+            "launch" => {
+                self.launch(args)?;
+                self.event("initialized", json!({}));
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let breakpoints = self.session()?.set_breakpoints(args);
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "configurationDone" => {
+                let session = self.session()?;
+                let pc = session.debugger.vm().pc();
+                if session.stop_on_entry {
+                    self.stopped("entry", None);
+                } else if session.debugger.breakpoints().any(|address| address == pc) {
+                    self.stopped("breakpoint", None);
+                } else {
+                    self.execute(|session| session.debugger.resume())?;
+                }
+                Ok(Value::Null)
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => {
+                let frame = self.session()?.frame();
+                Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+            }
+            "scopes" => {
+                let heap = self.session()?.debugger.vm().heap().len();
+                Ok(json!({ "scopes": [
+                    { "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+                    {
+                        "name": "Float registers",
+                        "variablesReference": FLOAT_REGISTERS,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Heap",
+                        "variablesReference": HEAP,
+                        "indexedVariables": (heap + HEAP_ROW - 1) / HEAP_ROW,
+                        "expensive": true,
+                    },
+                ]}))
+            }
+            "variables" => {
+                let variables = self.session()?.variables(args)?;
+                Ok(json!({ "variables": variables }))
+            }
+            "continue" => {
+                self.execute(|session| session.debugger.resume())?;
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" => {
+                self.step(args, Debugger::step_over)?;
+                Ok(Value::Null)
+            }
+            "stepIn" => {
+                self.step(args, Debugger::step_into)?;
+                Ok(Value::Null)
+            }
+            "stepOut" => {
+                self.execute(|session| session.debugger.step_out())?;
+                Ok(Value::Null)
+            }
+            // Running programs stop before the request is handled
+            "pause" => self.session().map(|_| Value::Null),
+            "disconnect" => {
+                self.session = None;
+                Ok(Value::Null)
+            }
+            _ => Err(format!("Unsupported request: {}", command)),
+        }
+    }
+
+    /// Loads the program to debug, assembling it if it's a source file.
+    fn launch(&mut self, args: &Value) -> Result<(), String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or_else(|| "Missing program to launch".to_string())?;
+        let path = PathBuf::from(program);
+        let bytes = fs::read(&path).map_err(|e| format!("{}: {}", program, e))?;
+        let mut executable = if bytes.starts_with(&MAGIC) {
+            Executable::from_bytes(&bytes).map_err(|e| format!("Invalid bytecode: {}", e))?
+        } else {
+            let source = String::from_utf8_lossy(&bytes);
+            assembler_for(&path)
+                .assemble(&source)
+                .map_err(|diagnostics| diagnostics.with_file(program).to_string())?
+        };
+        // Bytecode files name their main source, assembled ones don't
+        if executable
+            .debug_info
+            .files
+            .first()
+            .map_or(true, String::is_empty)
+        {
+            executable.debug_info.set_main_file(program);
+        }
+
+        let output = OutputBuffer::new();
+        let vm = VM::new()
+            .with_output(output.clone())
+            .with_input(io::empty());
+        let inbox = self.inbox.clone();
+        let mut debugger = Debugger::new(vm).with_interrupt(move || inbox.borrow_mut().poll());
+        debugger.load(executable);
+        self.session = Some(Session {
+            debugger,
+            output,
+            stop_on_entry: args["stopOnEntry"].as_bool().unwrap_or(false),
+            breakpoints: HashMap::new(),
+            terminated: false,
+        });
+        Ok(())
+    }
+
+    /// Steps by a source line, or by an instruction if requested.
+    fn step(
+        &mut self,
+        args: &Value,
+        step: fn(&mut Debugger) -> Result<StopReason, VmError>,
+    ) -> Result<(), String> {
+        let by_instruction = args["granularity"] == "instruction";
+        self.execute(|session| {
+            let pc = session.debugger.vm().pc();
+            let line = session.line();
+            loop {
+                let reason = step(&mut session.debugger)?;
+                // Also stop on a single line loop
+                let done = session.line() != line || session.debugger.vm().pc() == pc;
+                if by_instruction || line.is_none() || reason != StopReason::Step || done {
+                    return Ok(reason);
+                }
+            }
+        })
+    }
+
+    /// Runs the program, reporting its output and where it has stopped.
+    fn execute(
+        &mut self,
+        run: impl FnOnce(&mut Session) -> Result<StopReason, VmError>,
+    ) -> Result<(), String> {
+        let session = self.session()?;
+        let result = run(session);
+        let printed = session.output.take();
+        if !printed.is_empty() {
+            let output = String::from_utf8_lossy(&printed).into_owned();
+            self.event("output", json!({ "category": "stdout", "output": output }));
+        }
+        match result {
+            Ok(StopReason::Step) => self.stopped("step", None),
+            Ok(StopReason::Breakpoint(_)) => self.stopped("breakpoint", None),
+            Ok(StopReason::Paused) => self.stopped("pause", None),
+            Ok(reason @ StopReason::Watchpoint { .. }) => {
+                self.stopped("data breakpoint", Some(reason.to_string()))
+            }
+            Ok(StopReason::Exited(_)) => self.exited(0),
+            Err(e) => {
+                let location = self.session()?.debugger.vm().source_location(e.pc());
+                let output = match location {
+                    Some(location) => format!("VM error: {} ({})\n", e, location),
+                    None => format!("VM error: {}\n", e),
+                };
+                self.event("output", json!({ "category": "stderr", "output": output }));
+                self.exited(1);
+            }
+        }
+        Ok(())
+    }
+
+    fn exited(&mut self, code: i32) {
+        if let Some(session) = &mut self.session {
+            session.terminated = true;
+        }
+        self.event("exited", json!({ "exitCode": code }));
+        self.event("terminated", json!({}));
+    }
+
+    fn session(&mut self) -> Result<&mut Session, String> {
+        match &mut self.session {
+            Some(session) if !session.terminated => Ok(session),
+            _ => Err("No program is running".to_string()),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<String>) {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = Value::String(description);
+        }
+        self.event("stopped", body);
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.events
+            .push(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn respond(&mut self, request: &Value, result: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = Value::String(message),
+        }
+        self.send(response)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let content = message.to_string();
+        write!(
+            self.output,
+            "Content-Length: {}\r\n\r\n{}",
+            content.len(),
+            content
+        )?;
+        self.output.flush()
+    }
+}
+
+impl Session {
+    /// Returns the source file and line of the current instruction.
+    fn line(&self) -> Option<(u32, u32)> {
+        self.debug_info()
+            .location(self.debugger.vm().pc() as u32)
+            .map(|entry| (entry.file, entry.line))
+    }
+
+    /// Replaces breakpoints of a source file, moving each one
+    /// to the first instruction at or below its line.
+    fn set_breakpoints(&mut self, args: &Value) -> Vec<Value> {
+        let path = args["source"]["path"].as_str().unwrap_or_default();
+        let file = self.file_index(Path::new(path));
+        if let Some(file) = file {
+            for pc in self.breakpoints.remove(&file).unwrap_or_default() {
+                self.debugger.remove_breakpoint(pc);
+            }
+        }
+        let lines = args["breakpoints"]
+            .as_array()
+            .map_or(&[][..], Vec::as_slice);
+        lines
+            .iter()
+            .map(|breakpoint| {
+                let line = breakpoint["line"].as_u64().unwrap_or(0);
+                let entry = file.and_then(|file| self.debug_info().line_start(file, line as u32));
+                let entry = entry.copied();
+                match entry {
+                    Some(entry) => {
+                        let pc = entry.offset as usize;
+                        self.debugger.add_breakpoint(pc);
+                        self.breakpoints.entry(entry.file).or_default().push(pc);
+                        json!({ "verified": true, "line": entry.line, "column": entry.column })
+                    }
+                    None => json!({
+                        "verified": false,
+                        "line": line,
+                        "message": "No code at or below this line",
+                    }),
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the single stack frame, named after the closest
+    /// label before the current instruction.
+    fn frame(&self) -> Value {
+        let pc = self.debugger.vm().pc();
+        let name = self
+            .debugger
+            .symbols()
+            .iter()
+            .filter(|symbol| symbol.section == SectionKind::Code && symbol.offset as usize <= pc)
+            .max_by_key(|symbol| symbol.offset)
+            .map_or("program", |symbol| symbol.name.as_str());
+        let mut frame = json!({
+            "id": 0,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": pc.to_string(),
+        });
+        if let Some(entry) = self.debug_info().location(pc as u32) {
+            frame["source"] = self.source(entry.file);
+            frame["line"] = json!(entry.line);
+            frame["column"] = json!(entry.column);
+        }
+        frame
+    }
+
+    fn variables(&self, args: &Value) -> Result<Vec<Value>, String> {
+        let vm = self.debugger.vm();
+        let variable = |name: String, value: String| json!({ "name": name, "value": value, "variablesReference": 0 });
+        match args["variablesReference"].as_i64() {
+            Some(REGISTERS) => Ok(vm
+                .registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| variable(format!("${}", i), value.to_string()))
+                .collect()),
+            Some(FLOAT_REGISTERS) => Ok(vm
+                .float_registers
+                .iter()
+                .enumerate()
+                .map(|(i, value)| variable(format!("${}", i), value.to_string()))
+                .collect()),
+            Some(HEAP) => {
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = match args["count"].as_u64() {
+                    None | Some(0) => usize::MAX,
+                    Some(count) => count as usize,
+                };
+                Ok(vm
+                    .heap()
+                    .chunks(HEAP_ROW)
+                    .enumerate()
+                    .skip(start)
+                    .take(count)
+                    .map(|(i, row)| {
+                        let bytes: Vec<_> =
+                            row.iter().map(|byte| format!("{:02x}", byte)).collect();
+                        variable(format!("{:#06x}", i * HEAP_ROW), bytes.join(" "))
+                    })
+                    .collect())
+            }
+            _ => Err("Unknown variables reference".to_string()),
+        }
+    }
+
+    fn debug_info(&self) -> &DebugInfo {
+        self.debugger.vm().debug_info()
+    }
+
+    /// Returns the index of a source file in the debug info.
+    fn file_index(&self, path: &Path) -> Option<u32> {
+        let path = canonical(path);
+        self.debug_info()
+            .files
+            .iter()
+            .position(|file| canonical(Path::new(file)) == path)
+            .map(|i| i as u32)
+    }
+
+    fn source(&self, file: u32) -> Value {
+        let path = Path::new(&self.debug_info().files[file as usize]);
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        json!({ "name": name, "path": path.to_string_lossy() })
+    }
+}
+
+/// Reads the next message, none at the end of the input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| invalid_data("Missing Content-Length header"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content)
+        .map(Some)
+        .map_err(invalid_data)
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    /// Serves the requests, returning all the messages sent back.
+    fn serve(requests: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = json!(seq + 1);
+            request["type"] = json!("request");
+            let content = request.to_string();
+            write!(
+                input,
+                "Content-Length: {}\r\n\r\n{}",
+                content.len(),
+                content
+            )
+            .unwrap();
+        }
+        let mut output = vec![];
+        Server::new(Cursor::new(input), &mut output).run().unwrap();
+
+        let mut reader = Cursor::new(output);
+        let mut messages = vec![];
+        while let Some(message) = read_message(&mut reader).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    /// Temporary directory removed once the test is over.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let name = format!("iridium-dap-{}-{}", name, std::process::id());
+            let dir = std::env::temp_dir().join(name);
+            fs::create_dir_all(&dir).unwrap();
+            TempDir(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn request(command: &str, arguments: Value) -> Value {
+        json!({ "command": command, "arguments": arguments })
+    }
+
+    #[test]
+    fn test_session() {
+        let dir = TempDir::new("session");
+        let program = dir.0.join("main.iasm");
+        let lib = dir.0.join("lib.iasm");
+        fs::write(
+            &program,
+            "main: load $0 #20\n    alloc $0\n    load $0 #2\n    call @double\n    \
+             prti $0\n    hlt\n.include \"lib.iasm\"\n",
+        )
+        .unwrap();
+        fs::write(&lib, "double: add $0 $0 $0\n    ret\n").unwrap();
+        let source = |path: &Path| json!({ "path": path.to_string_lossy() });
+
+        let messages = serve(&[
+            request("initialize", json!({ "adapterID": "iridium" })),
+            request("launch", json!({ "program": program.to_string_lossy() })),
+            request(
+                "setBreakpoints",
+                json!({ "source": source(&program), "breakpoints": [{ "line": 5 }, { "line": 7 }] }),
+            ),
+            request(
+                "setBreakpoints",
+                json!({ "source": source(&lib), "breakpoints": [{ "line": 2 }] }),
+            ),
+            request("configurationDone", json!({})),
+            request("stackTrace", json!({ "threadId": 1 })),
+            request("scopes", json!({ "frameId": 0 })),
+            request("variables", json!({ "variablesReference": REGISTERS })),
+            request("stepOut", json!({ "threadId": 1 })),
+            request("next", json!({ "threadId": 1 })),
+            request("stackTrace", json!({ "threadId": 1 })),
+            request(
+                "variables",
+                json!({ "variablesReference": HEAP, "start": 1, "count": 1 }),
+            ),
+            request("continue", json!({ "threadId": 1 })),
+            request("stackTrace", json!({ "threadId": 1 })),
+            request("disconnect", json!({})),
+        ]);
+        let kinds: Vec<_> = messages
+            .iter()
+            .map(|message| match message["type"].as_str() {
+                Some("event") => format!("event {}", message["event"].as_str().unwrap()),
+                _ => format!(
+                    "{} {}",
+                    message["command"].as_str().unwrap(),
+                    message["success"]
+                ),
+            })
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "initialize true",
+                "launch true",
+                "event initialized",
+                "setBreakpoints true",
+                "setBreakpoints true",
+                "configurationDone true",
+                "event stopped",
+                "stackTrace true",
+                "scopes true",
+                "variables true",
+                "stepOut true",
+                "event stopped",
+                "next true",
+                "event output",
+                "event stopped",
+                "stackTrace true",
+                "variables true",
+                "continue true",
+                "event exited",
+                "event terminated",
+                "stackTrace false",
+                "disconnect true",
+            ]
+        );
+        let body = |i: usize| &messages[i]["body"];
+        assert_eq!(
+            body(3)["breakpoints"],
+            json!([
+                { "verified": true, "line": 5, "column": 5 },
+                { "verified": false, "line": 7, "message": "No code at or below this line" },
+            ])
+        );
+        assert_eq!(body(4)["breakpoints"][0]["verified"], true);
+        assert_eq!(body(6)["reason"], "breakpoint");
+        let frame = &body(7)["stackFrames"][0];
+        assert_eq!(frame["name"], "double");
+        assert_eq!(frame["line"], 2);
+        assert_eq!(frame["instructionPointerReference"], "20");
+        assert_eq!(frame["source"]["name"], "lib.iasm");
+        assert_eq!(body(8)["scopes"][2]["indexedVariables"], 2);
+        assert_eq!(
+            body(9)["variables"][0],
+            json!({ "name": "$0", "value": "4", "variablesReference": 0 })
+        );
+        assert_eq!(body(11)["reason"], "step");
+        assert_eq!(body(13)["output"], "4");
+        assert_eq!(body(14)["reason"], "step");
+        let frame = &body(15)["stackFrames"][0];
+        assert_eq!(
+            (&frame["name"], &frame["line"]),
+            (&json!("main"), &json!(6))
+        );
+        assert_eq!(frame["source"]["path"], program.to_string_lossy().as_ref());
+        assert_eq!(
+            body(16)["variables"],
+            json!([{ "name": "0x0010", "value": "00 00 00 00", "variablesReference": 0 }])
+        );
+        assert_eq!(body(18)["exitCode"], 0);
+    }
+
+    #[test]
+    fn test_bytecode() {
+        let dir = TempDir::new("bytecode");
+        let source = dir.0.join("fault.iasm");
+        let bytecode = dir.0.join("fault.ibc");
+        let mut executable = crate::assembler::Assembler::new()
+            .assemble("load $0 #1\n\ndiv $0 $1 $2\n")
+            .unwrap();
+        executable
+            .debug_info
+            .set_main_file(&source.to_string_lossy());
+        fs::write(&bytecode, executable.to_bytes()).unwrap();
+
+        let messages = serve(&[
+            request("launch", json!({ "program": bytecode.to_string_lossy() })),
+            request(
+                "setBreakpoints",
+                json!({ "source": { "path": source.to_string_lossy() }, "breakpoints": [{ "line": 2 }] }),
+            ),
+            request("configurationDone", json!({})),
+            request("continue", json!({ "threadId": 1 })),
+        ]);
+        assert_eq!(messages[2]["body"]["breakpoints"][0]["line"], 3);
+        assert_eq!(messages[4]["body"]["reason"], "breakpoint");
+        assert_eq!(messages[6]["body"]["category"], "stderr");
+        assert_eq!(
+            messages[6]["body"]["output"],
+            format!(
+                "VM error: Division by zero at 4 ({}:3:1)\n",
+                source.display()
+            )
+        );
+        assert_eq!(messages[7]["body"]["exitCode"], 1);
+        assert_eq!(messages[8]["event"], "terminated");
+    }
+
+    #[test]
+    fn test_pause() {
+        let dir = TempDir::new("pause");
+        let program = dir.0.join("loop.iasm");
+        fs::write(&program, "loop: jmp @loop\n").unwrap();
+
+        let messages = serve(&[
+            request("launch", json!({ "program": program.to_string_lossy() })),
+            request("configurationDone", json!({})),
+            request("pause", json!({ "threadId": 1 })),
+            request("stackTrace", json!({ "threadId": 1 })),
+        ]);
+        assert_eq!(messages[2]["command"], "configurationDone");
+        assert_eq!(messages[3]["event"], "stopped");
+        assert_eq!(messages[3]["body"]["reason"], "pause");
+        assert_eq!(messages[4]["command"], "pause");
+        assert_eq!(messages[4]["success"], true);
+        assert_eq!(messages[5]["body"]["stackFrames"][0]["name"], "loop");
+    }
+
+    #[test]
+    fn test_launch_errors() {
+        let messages = serve(&[
+            request("launch", json!({})),
+            request("launch", json!({ "program": "/nonexistent/main.iasm" })),
+            request("threads", json!({})),
+        ]);
+        assert_eq!(messages[0]["success"], false);
+        assert_eq!(messages[0]["message"], "Missing program to launch");
+        assert_eq!(messages[1]["success"], false);
+        assert_eq!(messages[2]["success"], true);
+    }
+}
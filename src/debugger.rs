@@ -0,0 +1,466 @@
+use crate::bytecode::{Executable, SectionKind, Symbol};
+use crate::instruction::{decode, Opcode};
+use crate::vm::{ExitReason, VmError, VM};
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+/// Number of instructions executed between checks for an interrupt.
+const SLICE: usize = 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugError {
+    /// No code label with such name.
+    UndefinedLabel(String),
+    /// Register index out of range.
+    InvalidRegister(usize),
+    /// No instructions at or below the line of the main source.
+    NoCodeAtLine(u32),
+}
+
+impl Display for DebugError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DebugError::UndefinedLabel(name) => write!(f, "Undefined label: {}", name),
+            DebugError::InvalidRegister(register) => write!(f, "Invalid register ${}", register),
+            DebugError::NoCodeAtLine(line) => write!(f, "No code at or below line {}", line),
+        }
+    }
+}
+
+impl Error for DebugError {}
+
+/// Location the debugger watches for changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    Register(usize),
+    /// Range of heap bytes, which may not be allocated yet.
+    Heap {
+        address: usize,
+        len: usize,
+    },
+}
+
+impl Watchpoint {
+    /// Reads the watched value, empty for unallocated heap.
+    fn read(&self, vm: &VM) -> Vec<u8> {
+        match *self {
+            Watchpoint::Register(register) => vm.registers[register].to_be_bytes().to_vec(),
+            Watchpoint::Heap { address, len } => address
+                .checked_add(len)
+                .and_then(|end| vm.heap().get(address..end))
+                .map_or(vec![], |bytes| bytes.to_vec()),
+        }
+    }
+
+    /// Formats a value read by `read`.
+    fn format(&self, value: &[u8]) -> String {
+        match self {
+            Watchpoint::Register(_) => {
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(value);
+                i32::from_be_bytes(bytes).to_string()
+            }
+            Watchpoint::Heap { .. } if value.is_empty() => "unallocated".to_string(),
+            Watchpoint::Heap { .. } => format!("{:?}", value),
+        }
+    }
+}
+
+impl Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Register(register) => write!(f, "${}", register),
+            Watchpoint::Heap { address, len } => {
+                write!(f, "heap[{}..{}]", address, address.saturating_add(*len))
+            }
+        }
+    }
+}
+
+/// Reason why the debugger has given control back.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StopReason {
+    /// Step has been completed.
+    Step,
+    /// Next instruction to execute has a breakpoint.
+    Breakpoint(usize),
+    /// Last executed instruction has changed a watched value.
+    Watchpoint {
+        watchpoint: Watchpoint,
+        old: Vec<u8>,
+        new: Vec<u8>,
+    },
+    /// Run has been interrupted.
+    Paused,
+    /// Program has stopped.
+    Exited(ExitReason),
+}
+
+impl Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Step => write!(f, "Step completed"),
+            StopReason::Breakpoint(pc) => write!(f, "Breakpoint at {}", pc),
+            StopReason::Watchpoint {
+                watchpoint,
+                old,
+                new,
+            } => write!(
+                f,
+                "Watchpoint {} changed: {} -> {}",
+                watchpoint,
+                watchpoint.format(old),
+                watchpoint.format(new)
+            ),
+            StopReason::Paused => write!(f, "Paused"),
+            StopReason::Exited(ExitReason::Halted) => write!(f, "Program halted"),
+            StopReason::Exited(ExitReason::EndOfProgram) => write!(f, "Program ended"),
+        }
+    }
+}
+
+/// Runs a VM under control of breakpoints and watchpoints.
+///
+/// Breakpoints stop execution before the instruction at their
+/// address, watchpoints right after the instruction changing them.
+#[derive(Default)]
+pub struct Debugger {
+    vm: VM,
+    /// Symbols of the loaded executable, to resolve labels.
+    symbols: Vec<Symbol>,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: Vec<Watchpoint>,
+    /// Asked between slices of a run whether to pause it.
+    interrupt: Option<Box<dyn FnMut() -> bool>>,
+}
+
+impl Debugger {
+    pub fn new(vm: VM) -> Debugger {
+        Debugger {
+            vm,
+            ..Debugger::default()
+        }
+    }
+
+    /// Checks every `SLICE` instructions of a run whether to pause it,
+    /// so that a long run can be stopped from outside.
+    pub fn with_interrupt(mut self, interrupt: impl FnMut() -> bool + 'static) -> Debugger {
+        self.interrupt = Some(Box::new(interrupt));
+        self
+    }
+
+    pub fn vm(&self) -> &VM {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut VM {
+        &mut self.vm
+    }
+
+    /// Loads an executable into the VM, keeping its symbols
+    /// for label breakpoints.
+    pub fn load(&mut self, executable: Executable) {
+        self.symbols = executable.symbols.clone();
+        self.vm.load_executable(executable);
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    /// Returns the code address of a label of the loaded executable.
+    pub fn label_address(&self, name: &str) -> Result<usize, DebugError> {
+        self.symbols
+            .iter()
+            .find(|symbol| symbol.name == name && symbol.section == SectionKind::Code)
+            .map(|symbol| symbol.offset as usize)
+            .ok_or_else(|| DebugError::UndefinedLabel(name.to_string()))
+    }
+
+    /// Returns the address of the first instruction at or below
+    /// the line of the main source, using the executable debug info.
+    pub fn line_address(&self, line: u32) -> Result<usize, DebugError> {
+        self.vm
+            .debug_info()
+            .line_start(0, line)
+            .map(|entry| entry.offset as usize)
+            .ok_or(DebugError::NoCodeAtLine(line))
+    }
+
+    /// Adds a breakpoint, returns false if it's already set.
+    pub fn add_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.insert(pc)
+    }
+
+    /// Adds a breakpoint at a label, returning its address.
+    pub fn add_label_breakpoint(&mut self, name: &str) -> Result<usize, DebugError> {
+        let pc = self.label_address(name)?;
+        self.breakpoints.insert(pc);
+        Ok(pc)
+    }
+
+    /// Removes a breakpoint, returns false if it wasn't set.
+    pub fn remove_breakpoint(&mut self, pc: usize) -> bool {
+        self.breakpoints.remove(&pc)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = usize> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    /// Adds a watchpoint, unless it's already set.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> Result<(), DebugError> {
+        if let Watchpoint::Register(register) = watchpoint {
+            if register >= self.vm.registers.len() {
+                return Err(DebugError::InvalidRegister(register));
+            }
+        }
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+        Ok(())
+    }
+
+    /// Removes a watchpoint, returns false if it wasn't set.
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| *w != watchpoint);
+        self.watchpoints.len() != len
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    /// Executes a single instruction, entering called subroutines.
+    pub fn step_into(&mut self) -> Result<StopReason, VmError> {
+        self.run_until(|_, _| true)
+    }
+
+    /// Executes a single instruction, running called subroutines
+    /// up to their return unless they hit a breakpoint or a watchpoint.
+    pub fn step_over(&mut self) -> Result<StopReason, VmError> {
+        let pc = self.vm.pc();
+        match self.vm.program.get(pc..).map(decode) {
+            Some(Ok((opcode @ (Opcode::CALL | Opcode::CALLI), _))) => {
+                let next = pc + opcode.info().instruction_len();
+                let sp = self.vm.sp();
+                self.run_until(|_, vm| vm.pc() == next && vm.sp() == sp)
+            }
+            _ => self.step_into(),
+        }
+    }
+
+    /// Runs the current subroutine up to its return, i.e. until a `RET`
+    /// pops the stack below its current depth.
+    pub fn step_out(&mut self) -> Result<StopReason, VmError> {
+        let sp = self.vm.sp();
+        self.run_until(|pc, vm| {
+            vm.sp() < sp && matches!(vm.program.get(pc..).map(decode), Some(Ok((Opcode::RET, _))))
+        })
+    }
+
+    /// Runs the program until a breakpoint, a watchpoint or its end.
+    pub fn resume(&mut self) -> Result<StopReason, VmError> {
+        self.run_until(|_, _| false)
+    }
+
+    /// Executes instructions until `done` holds after one of them,
+    /// which is given the address of the executed instruction.
+    ///
+    /// The breakpoint at the current instruction is ignored,
+    /// so that resuming from a breakpoint makes progress.
+    fn run_until(&mut self, done: impl Fn(usize, &VM) -> bool) -> Result<StopReason, VmError> {
+        let mut executed = 0;
+        loop {
+            let pc = self.vm.pc();
+            if executed > 0 && self.breakpoints.contains(&pc) {
+                return Ok(StopReason::Breakpoint(pc));
+            }
+            if executed > 0 && executed % SLICE == 0 {
+                if let Some(interrupt) = &mut self.interrupt {
+                    if interrupt() {
+                        return Ok(StopReason::Paused);
+                    }
+                }
+            }
+            executed += 1;
+            let values: Vec<_> = self.watchpoints.iter().map(|w| w.read(&self.vm)).collect();
+            if let Some(reason) = self.vm.step()? {
+                return Ok(StopReason::Exited(reason));
+            }
+            for (watchpoint, old) in self.watchpoints.iter().zip(values) {
+                let new = watchpoint.read(&self.vm);
+                if new != old {
+                    return Ok(StopReason::Watchpoint {
+                        watchpoint: *watchpoint,
+                        old,
+                        new,
+                    });
+                }
+            }
+            if done(pc, &self.vm) {
+                return Ok(StopReason::Step);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    const PROGRAM: &str = "
+    main: load $0 #2
+        call @double
+        inc $1
+        hlt
+    double: add $0 $0 $0
+        ret
+    ";
+
+    fn debugger(source: &str) -> Debugger {
+        let mut debugger = Debugger::new(VM::new());
+        debugger.load(Assembler::new().assemble(source).unwrap());
+        debugger
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger(PROGRAM);
+        assert_eq!(debugger.add_label_breakpoint("double"), Ok(10));
+        assert!(debugger.add_breakpoint(9));
+        assert!(!debugger.add_breakpoint(9));
+        assert_eq!(debugger.resume(), Ok(StopReason::Breakpoint(10)));
+        assert_eq!(debugger.vm().sp(), 1);
+        assert_eq!(debugger.resume(), Ok(StopReason::Breakpoint(9)));
+        assert!(debugger.remove_breakpoint(9));
+        assert!(!debugger.remove_breakpoint(9));
+        assert_eq!(debugger.breakpoints().collect::<Vec<_>>(), vec![10]);
+        assert_eq!(
+            debugger.resume(),
+            Ok(StopReason::Exited(ExitReason::Halted))
+        );
+        assert_eq!(debugger.vm().registers[..2], [4, 1]);
+        assert_eq!(
+            debugger.add_label_breakpoint("missing"),
+            Err(DebugError::UndefinedLabel("missing".to_string()))
+        );
+        assert_eq!(debugger.line_address(4), Ok(7));
+        assert_eq!(debugger.line_address(5), Ok(9));
+        assert_eq!(debugger.line_address(8), Err(DebugError::NoCodeAtLine(8)));
+    }
+
+    #[test]
+    fn test_stepping() {
+        let mut debugger = debugger(PROGRAM);
+        assert_eq!(debugger.step_into(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 4);
+        assert_eq!(debugger.step_over(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 7);
+        assert_eq!(debugger.vm().registers[0], 4);
+
+        let mut debugger = self::debugger(PROGRAM);
+        debugger.step_into().unwrap();
+        assert_eq!(debugger.step_into(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 10);
+        debugger.step_into().unwrap();
+        assert_eq!(debugger.step_into(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 7);
+
+        let mut debugger = self::debugger(
+            "
+            call @outer
+            hlt
+        outer: load $0 #1
+            push $0
+            call @inner
+            pop $0
+            ret
+        inner: push $0
+            pop $1
+            ret
+            ",
+        );
+        debugger.add_label_breakpoint("inner").unwrap();
+        debugger.resume().unwrap();
+        assert_eq!(debugger.vm().sp(), 3);
+        assert_eq!(debugger.step_out(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 13);
+        assert_eq!(debugger.step_out(), Ok(StopReason::Step));
+        assert_eq!(debugger.vm().pc(), 3);
+        assert_eq!(debugger.vm().sp(), 0);
+        assert_eq!(
+            debugger.step_out(),
+            Ok(StopReason::Exited(ExitReason::Halted))
+        );
+
+        let mut debugger = self::debugger("loop: jmp @loop\n");
+        let mut polls = 0;
+        debugger = debugger.with_interrupt(move || {
+            polls += 1;
+            polls % 3 == 0
+        });
+        assert_eq!(debugger.resume(), Ok(StopReason::Paused));
+        assert_eq!(debugger.vm().pc(), 0);
+        assert_eq!(debugger.resume(), Ok(StopReason::Paused));
+
+        let mut debugger = self::debugger(PROGRAM);
+        debugger.add_breakpoint(14);
+        debugger.step_into().unwrap();
+        assert_eq!(debugger.step_over(), Ok(StopReason::Breakpoint(14)));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger(PROGRAM);
+        debugger.add_watchpoint(Watchpoint::Register(1)).unwrap();
+        let reason = debugger.resume().unwrap();
+        assert_eq!(
+            reason,
+            StopReason::Watchpoint {
+                watchpoint: Watchpoint::Register(1),
+                old: vec![0; 4],
+                new: vec![0, 0, 0, 1],
+            }
+        );
+        assert_eq!(reason.to_string(), "Watchpoint $1 changed: 0 -> 1");
+        assert_eq!(debugger.vm().pc(), 9);
+        assert!(debugger.remove_watchpoint(Watchpoint::Register(1)));
+        assert_eq!(
+            debugger.add_watchpoint(Watchpoint::Register(32)),
+            Err(DebugError::InvalidRegister(32))
+        );
+
+        let mut debugger = self::debugger("load $0 #4\nalloc $0\nload $1 #7\nstb $1 $0 #-2\nhlt\n");
+        let watchpoint = Watchpoint::Heap { address: 2, len: 2 };
+        debugger.add_watchpoint(watchpoint).unwrap();
+        let reason = debugger.resume().unwrap();
+        assert_eq!(
+            reason.to_string(),
+            "Watchpoint heap[2..4] changed: unallocated -> [0, 0]"
+        );
+        let reason = debugger.resume().unwrap();
+        assert_eq!(
+            reason.to_string(),
+            "Watchpoint heap[2..4] changed: [0, 0] -> [7, 0]"
+        );
+        assert_eq!(
+            debugger.resume(),
+            Ok(StopReason::Exited(ExitReason::Halted))
+        );
+
+        let mut debugger = self::debugger("load $0 #4\nalloc $0\nhlt\n");
+        let watchpoint = Watchpoint::Heap {
+            address: 2,
+            len: usize::MAX,
+        };
+        debugger.add_watchpoint(watchpoint).unwrap();
+        assert_eq!(
+            debugger.resume(),
+            Ok(StopReason::Exited(ExitReason::Halted))
+        );
+    }
+}
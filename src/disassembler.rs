@@ -0,0 +1,286 @@
+use crate::assembler::ENTRY_LABEL;
+use crate::bytecode::{DebugInfo, Executable, SectionKind};
+use crate::instruction::{decode, Opcode, Operand, Operands};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+/// Maximum number of values in a single `.bytes` line.
+const BYTES_PER_LINE: usize = 16;
+
+/// Turns an executable back into assembly source.
+///
+/// The output can be assembled again into the same executable:
+/// jump and call targets become labels, named after the executable
+/// symbols where available and synthesized otherwise.
+/// Bytes that don't decode and instructions with non-finite float
+/// operands, which have no literal syntax, are written as `.bytes`.
+/// Instructions are annotated with their source locations
+/// if the executable has debug info.
+pub fn disassemble(executable: &Executable) -> String {
+    let mut code_labels = HashMap::new();
+    let mut data_labels = HashMap::new();
+    for symbol in &executable.symbols {
+        let labels = match symbol.section {
+            SectionKind::ReadOnlyData => &mut data_labels,
+            _ => &mut code_labels,
+        };
+        labels
+            .entry(symbol.offset)
+            .or_insert_with(|| symbol.name.clone());
+    }
+    if executable.entry_point != 0 {
+        code_labels.insert(executable.entry_point, ENTRY_LABEL.to_string());
+    }
+
+    let mut source = String::new();
+    if !executable.ro_data.is_empty() {
+        source.push_str(".data\n");
+        disassemble_data(&executable.ro_data, &data_labels, &mut source);
+        source.push_str(".code\n");
+    }
+    disassemble_code(
+        &executable.code,
+        code_labels,
+        &data_labels,
+        &executable.debug_info,
+        &mut source,
+    );
+    source
+}
+
+/// Disassembles raw program bytecode without any symbols.
+pub fn disassemble_program(code: &[u8]) -> String {
+    let mut source = String::new();
+    disassemble_code(
+        code,
+        HashMap::new(),
+        &HashMap::new(),
+        &DebugInfo::default(),
+        &mut source,
+    );
+    source
+}
+
+/// Formats a single decoded instruction, using `labels`
+/// to name address operands.
+pub fn format_instruction(
+    opcode: Opcode,
+    operands: &Operands,
+    labels: &HashMap<u32, String>,
+) -> String {
+    let mut text = opcode.mnemonic().to_string();
+    for operand in operands.as_slice() {
+        text.push(' ');
+        match operand {
+            Operand::Register(reg) | Operand::FloatRegister(reg) => {
+                write!(text, "${}", reg).unwrap()
+            }
+            Operand::Immediate(value) => write!(text, "#{}", value).unwrap(),
+            Operand::Word(value) => write!(text, "#{}", value).unwrap(),
+            Operand::Address(address) => match labels.get(&(*address as u32)) {
+                Some(name) => write!(text, "@{}", name).unwrap(),
+                None => write!(text, "#{}", address).unwrap(),
+            },
+            Operand::Float(value) => text.push_str(&format_float(*value)),
+        }
+    }
+    text
+}
+
+fn disassemble_code(
+    code: &[u8],
+    mut labels: HashMap<u32, String>,
+    data_labels: &HashMap<u32, String>,
+    debug_info: &DebugInfo,
+    source: &mut String,
+) {
+    // First pass: find instruction boundaries and jump targets
+    let mut offsets = vec![];
+    let mut targets = vec![];
+    let mut pc = 0;
+    while pc < code.len() {
+        offsets.push(pc);
+        match decode(&code[pc..]) {
+            Ok((opcode, operands)) => {
+                let operands = match opcode.reads_data() {
+                    true => &[][..],
+                    false => operands.as_slice(),
+                };
+                for operand in operands {
+                    if let Operand::Address(address) = operand {
+                        targets.push(*address as usize);
+                    }
+                }
+                pc += opcode.info().instruction_len();
+            }
+            Err(_) => pc += 1,
+        }
+    }
+    // Labels can only be declared on instruction boundaries
+    for target in targets {
+        if offsets.binary_search(&target).is_ok() {
+            labels
+                .entry(target as u32)
+                .or_insert_with(|| format!("L{}", target));
+        }
+    }
+
+    for &pc in &offsets {
+        let label = labels.get(&(pc as u32));
+        let mut text = match decode(&code[pc..]) {
+            Ok((opcode, operands)) if has_non_finite(&operands) => {
+                let end = pc + opcode.info().instruction_len();
+                let instruction = format_instruction(opcode, &operands, &labels);
+                format!("{} ; {}", format_bytes(&code[pc..end]), instruction)
+            }
+            Ok((opcode, operands)) if opcode.reads_data() => {
+                format_instruction(opcode, &operands, data_labels)
+            }
+            Ok((opcode, operands)) => format_instruction(opcode, &operands, &labels),
+            Err(e) => format!("{} ; invalid byte ({:?})", format_bytes(&code[pc..=pc]), e),
+        };
+        if let Some(location) = debug_info.format_location(pc as u32) {
+            write!(text, " ; {}", location).unwrap();
+        }
+        match label {
+            Some(name) => writeln!(source, "{}: {}", name, text).unwrap(),
+            None => writeln!(source, "    {}", text).unwrap(),
+        }
+    }
+}
+
+fn disassemble_data(data: &[u8], labels: &HashMap<u32, String>, source: &mut String) {
+    let mut start = 0;
+    while start < data.len() {
+        // Split lines at labels, so each of them stays at its offset
+        let mut end = (start + BYTES_PER_LINE).min(data.len());
+        if let Some(next) = (start + 1..end).find(|i| labels.contains_key(&(*i as u32))) {
+            end = next;
+        }
+        let text = format_bytes(&data[start..end]);
+        match labels.get(&(start as u32)) {
+            Some(name) => writeln!(source, "{}: {}", name, text).unwrap(),
+            None => writeln!(source, "    {}", text).unwrap(),
+        }
+        start = end;
+    }
+}
+
+fn format_bytes(bytes: &[u8]) -> String {
+    let mut text = ".bytes".to_string();
+    for byte in bytes {
+        write!(text, " #{}", byte).unwrap();
+    }
+    text
+}
+
+/// Returns true if any of the operands is a NaN or an infinity.
+fn has_non_finite(operands: &Operands) -> bool {
+    operands
+        .as_slice()
+        .iter()
+        .any(|operand| matches!(operand, Operand::Float(value) if !value.is_finite()))
+}
+
+/// Formats a float literal so that the assembler parses it back,
+/// unless it's not finite.
+fn format_float(value: f64) -> String {
+    let text = format!("{:?}", value);
+    if text.contains('.') {
+        format!("#{}", text)
+    } else {
+        format!("#{}", text.replacen('e', ".0e", 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assert_roundtrip(source: &str) -> String {
+        let executable = Assembler::new().assemble(source).unwrap();
+        let text = disassemble(&executable);
+        let reassembled = Assembler::new().assemble(&text).unwrap();
+        assert_eq!(reassembled.code, executable.code, "{}", text);
+        assert_eq!(reassembled.ro_data, executable.ro_data, "{}", text);
+        assert_eq!(reassembled.entry_point, executable.entry_point, "{}", text);
+        text
+    }
+
+    #[test]
+    fn test_disassemble_program() {
+        let code = vec![Opcode::LOAD.into(), 0, 1, 244, Opcode::HLT.into()];
+        assert_eq!(disassemble_program(&code), "    load $0 #500\n    hlt\n");
+    }
+
+    #[test]
+    fn test_disassemble_labels() {
+        let text = assert_roundtrip(
+            "
+            load $0 #3
+        loop: dec $0
+            eq $0 $1
+            jneq @loop
+            call @end
+        end: hlt
+            ",
+        );
+        assert!(text.contains("loop: dec $0 ; <input>:3:9"));
+        assert!(text.contains("jneq @loop"));
+        assert!(text.contains("end: hlt"));
+    }
+
+    #[test]
+    fn test_disassemble_synthesized_labels() {
+        let code = vec![Opcode::JMPI.into(), 0, 3, Opcode::HLT.into()];
+        let text = disassemble_program(&code);
+        assert_eq!(text, "    jmp @L3\nL3: hlt\n");
+    }
+
+    #[test]
+    fn test_disassemble_data_and_floats() {
+        let text = assert_roundtrip(
+            "
+        .data
+        hello: .asciiz \"Hello, world!!!!!!\"
+        nums: .integer #1 #2
+        .code
+            loadf64 $1 #1.5
+            loadf64 $2 #1.0e300
+            load $3 #-5
+            loadw $4 #-70000
+        main: load $0 @nums
+            prts @hello
+            hlt
+            ",
+        );
+        assert!(text.contains("prts @hello"));
+        assert!(text.contains("nums: .bytes #0 #0 #0 #1"));
+        assert!(text.contains("main: load $0 #19"));
+        assert!(text.contains("load $3 #-5"));
+        assert!(text.contains("loadw $4 #-70000"));
+    }
+
+    #[test]
+    fn test_disassemble_invalid() {
+        let code = vec![200, Opcode::JMPI.into(), 0, 0, Opcode::HLT.into()];
+        let text = disassemble_program(&code);
+        assert_eq!(
+            text,
+            "L0: .bytes #200 ; invalid byte (IllegalOpcode(200))\n    jmp @L0\n    hlt\n"
+        );
+        assert_eq!(Assembler::new().assemble(&text).unwrap().code, code);
+
+        let mut executable = Assembler::new()
+            .assemble("loadf64 $1 #1.5\nloadf64 $2 #2.5\nhlt\n")
+            .unwrap();
+        executable.code[2..10].copy_from_slice(&f64::NAN.to_be_bytes());
+        executable.code[12..20].copy_from_slice(&f64::NEG_INFINITY.to_be_bytes());
+        let text = disassemble(&executable);
+        assert!(text.contains("; loadf64 $1 #NaN"), "{}", text);
+        assert!(text.contains("; loadf64 $2 #-inf"), "{}", text);
+        let reassembled = Assembler::new().assemble(&text).unwrap();
+        assert_eq!(reassembled.code, executable.code, "{}", text);
+    }
+}
@@ -1,16 +1,34 @@
+use std::fmt::{self, Display};
+
 /// VM opcodes.
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Opcode {
     /// No operation.
     NOP,
-    /// Load a number into register.
+    /// Load a sign-extended 16-bit number into register.
     LOAD,
+    /// Load a 32-bit number into register.
+    LOADW,
     /// Allocate a chunk of memory from a heap.
     ALLOC,
     ADD,
     SUB,
     MUL,
     DIV,
+    /// Bitwise and.
+    AND,
+    /// Bitwise or.
+    OR,
+    /// Bitwise exclusive or.
+    XOR,
+    /// Bitwise negation.
+    NOT,
+    /// Shift left.
+    SHL,
+    /// Logical shift right.
+    SHR,
+    /// Arithmetic shift right.
+    SAR,
     /// Abolute jump.
     JMP,
     /// Forward relative jump.
@@ -19,6 +37,16 @@ pub enum Opcode {
     JMPB,
     /// Equality comparison.
     EQ,
+    /// Inequality comparison.
+    NEQ,
+    /// Greater than comparison.
+    GT,
+    /// Less than comparison.
+    LT,
+    /// Greater than or equal comparison.
+    GTE,
+    /// Less than or equal comparison.
+    LTE,
     /// Jump if equal.
     JEQ,
     /// Jump if not equal.
@@ -27,81 +55,445 @@ pub enum Opcode {
     INC,
     /// Decrement.
     DEC,
+    /// Abolute jump to an address immediate.
+    JMPI,
+    /// Jump to an address immediate if equal.
+    JEQI,
+    /// Jump to an address immediate if not equal.
+    JNEQI,
+    /// Load a byte from the heap, zero-extended.
+    LDB,
+    /// Load a halfword from the heap, zero-extended.
+    LDH,
+    /// Load a word from the heap.
+    LDW,
+    /// Store a low byte of a register to the heap.
+    STB,
+    /// Store a low halfword of a register to the heap.
+    STH,
+    /// Store a word to the heap.
+    STW,
+    /// Call a subroutine at an address held in a register.
+    CALL,
+    /// Call a subroutine at an address immediate.
+    CALLI,
+    /// Return from a subroutine.
+    RET,
+    /// Push a register onto the stack.
+    PUSH,
+    /// Pop a value from the stack into a register.
+    POP,
+    /// Load a float number into a float register.
+    LOADF64,
+    ADDF64,
+    SUBF64,
+    MULF64,
+    DIVF64,
+    /// Float equality comparison.
+    EQF64,
+    /// Float inequality comparison.
+    NEQF64,
+    /// Float greater than comparison.
+    GTF64,
+    /// Float greater than or equal comparison.
+    GTEF64,
+    /// Float less than comparison.
+    LTF64,
+    /// Float less than or equal comparison.
+    LTEF64,
+    /// Convert an integer register into a float register.
+    ITOF,
+    /// Convert a float register into an integer register, truncating.
+    FTOI,
+    /// Print a NUL-terminated string from the heap
+    /// at an address held in a register.
+    PRTS,
+    /// Print a NUL-terminated string from the read-only data
+    /// at an address immediate.
+    PRTSI,
+    /// Print a register as a decimal integer.
+    PRTI,
+    /// Read a byte of input into a register, or -1 at the end of input.
+    READ,
+    /// Call a host function registered on the VM under the number
+    /// immediate. The immediate is taken as unsigned.
+    SYSCALL,
     /// Halt VM execution.
     HLT,
     /// Illegal opcode encountered.
     IGL,
 }
 
+/// Number of registers in each of the VM register files.
+pub const REGISTER_COUNT: usize = 32;
+
+/// Maximum number of operands an instruction can have.
+pub const MAX_OPERANDS: usize = 3;
+
+/// Kind of an instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// Index of an integer register.
+    Register,
+    /// Index of a float register.
+    FloatRegister,
+    /// Signed 16-bit immediate number, two's complement.
+    Immediate,
+    /// Unsigned 16-bit absolute address, in code unless
+    /// the opcode reads data, see `Opcode::reads_data`.
+    Address,
+    /// Signed 32-bit immediate number, two's complement.
+    Word,
+    /// 64-bit float immediate.
+    Float,
+}
+
+impl OperandKind {
+    /// Returns a range of integer values the operand can hold,
+    /// if it is an integer one.
+    pub fn range(&self) -> Option<(i64, i64)> {
+        match self {
+            OperandKind::Immediate => Some((i16::MIN as i64, i16::MAX as i64)),
+            OperandKind::Address => Some((0, u16::MAX as i64)),
+            OperandKind::Word => Some((i32::MIN as i64, i32::MAX as i64)),
+            _ => None,
+        }
+    }
+
+    /// Returns a number of bytes the operand occupies.
+    pub const fn size(&self) -> usize {
+        match self {
+            OperandKind::Register | OperandKind::FloatRegister => 1,
+            OperandKind::Immediate | OperandKind::Address => 2,
+            OperandKind::Word => 4,
+            OperandKind::Float => 8,
+        }
+    }
+}
+
+impl Display for OperandKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            OperandKind::Register => "register",
+            OperandKind::FloatRegister => "float register",
+            OperandKind::Immediate => "integer",
+            OperandKind::Address => "label",
+            OperandKind::Word => "word",
+            OperandKind::Float => "float",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Encoding metadata of an opcode.
+///
+/// Every instruction of an opcode has a fixed length: one opcode byte
+/// followed by its operands in order, all multi-byte values are big-endian.
+#[derive(Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    pub code: u8,
+    /// Assembly mnemonic. Opcodes sharing a mnemonic
+    /// are told apart by their operand kinds.
+    pub mnemonic: &'static str,
+    pub operands: &'static [OperandKind],
+}
+
+impl OpcodeInfo {
+    const fn new(
+        opcode: Opcode,
+        code: u8,
+        mnemonic: &'static str,
+        operands: &'static [OperandKind],
+    ) -> OpcodeInfo {
+        OpcodeInfo {
+            opcode,
+            code,
+            mnemonic,
+            operands,
+        }
+    }
+
+    /// Returns a total number of bytes of an instruction.
+    pub fn instruction_len(&self) -> usize {
+        1 + self.operands.iter().map(OperandKind::size).sum::<usize>()
+    }
+}
+
+use OperandKind::{Address, Float, FloatRegister, Immediate, Register, Word};
+
+/// Encoding table used by the assembler, the VM and the disassembler.
+pub const OPCODES: &[OpcodeInfo] = &[
+    OpcodeInfo::new(Opcode::NOP, 0, "nop", &[]),
+    OpcodeInfo::new(Opcode::LOAD, 1, "load", &[Register, Immediate]),
+    OpcodeInfo::new(Opcode::ALLOC, 2, "alloc", &[Register]),
+    OpcodeInfo::new(Opcode::ADD, 3, "add", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::SUB, 4, "sub", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::MUL, 5, "mul", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::DIV, 6, "div", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::JMP, 7, "jmp", &[Register]),
+    OpcodeInfo::new(Opcode::JMPF, 8, "jmpf", &[Register]),
+    OpcodeInfo::new(Opcode::JMPB, 9, "jmpb", &[Register]),
+    OpcodeInfo::new(Opcode::EQ, 10, "eq", &[Register, Register]),
+    OpcodeInfo::new(Opcode::JEQ, 11, "jeq", &[Register]),
+    OpcodeInfo::new(Opcode::JNEQ, 12, "jneq", &[Register]),
+    OpcodeInfo::new(Opcode::INC, 13, "inc", &[Register]),
+    OpcodeInfo::new(Opcode::DEC, 14, "dec", &[Register]),
+    OpcodeInfo::new(Opcode::JMPI, 15, "jmp", &[Address]),
+    OpcodeInfo::new(Opcode::JEQI, 16, "jeq", &[Address]),
+    OpcodeInfo::new(Opcode::JNEQI, 17, "jneq", &[Address]),
+    OpcodeInfo::new(Opcode::LDB, 18, "ldb", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::LDH, 19, "ldh", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::LDW, 20, "ldw", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::STB, 21, "stb", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::STH, 22, "sth", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::STW, 23, "stw", &[Register, Register, Immediate]),
+    OpcodeInfo::new(Opcode::CALL, 24, "call", &[Register]),
+    OpcodeInfo::new(Opcode::CALLI, 25, "call", &[Address]),
+    OpcodeInfo::new(Opcode::RET, 26, "ret", &[]),
+    OpcodeInfo::new(Opcode::PUSH, 27, "push", &[Register]),
+    OpcodeInfo::new(Opcode::POP, 28, "pop", &[Register]),
+    OpcodeInfo::new(Opcode::NEQ, 29, "neq", &[Register, Register]),
+    OpcodeInfo::new(Opcode::GT, 30, "gt", &[Register, Register]),
+    OpcodeInfo::new(Opcode::LT, 31, "lt", &[Register, Register]),
+    OpcodeInfo::new(Opcode::GTE, 32, "gte", &[Register, Register]),
+    OpcodeInfo::new(Opcode::LTE, 33, "lte", &[Register, Register]),
+    OpcodeInfo::new(Opcode::AND, 34, "and", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::OR, 35, "or", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::XOR, 36, "xor", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::NOT, 37, "not", &[Register, Register]),
+    OpcodeInfo::new(Opcode::SHL, 38, "shl", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::SHR, 39, "shr", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::SAR, 40, "sar", &[Register, Register, Register]),
+    OpcodeInfo::new(Opcode::LOADF64, 41, "loadf64", &[FloatRegister, Float]),
+    OpcodeInfo::new(
+        Opcode::ADDF64,
+        42,
+        "addf64",
+        &[FloatRegister, FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(
+        Opcode::SUBF64,
+        43,
+        "subf64",
+        &[FloatRegister, FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(
+        Opcode::MULF64,
+        44,
+        "mulf64",
+        &[FloatRegister, FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(
+        Opcode::DIVF64,
+        45,
+        "divf64",
+        &[FloatRegister, FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(Opcode::EQF64, 46, "eqf64", &[FloatRegister, FloatRegister]),
+    OpcodeInfo::new(
+        Opcode::NEQF64,
+        47,
+        "neqf64",
+        &[FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(Opcode::GTF64, 48, "gtf64", &[FloatRegister, FloatRegister]),
+    OpcodeInfo::new(
+        Opcode::GTEF64,
+        49,
+        "gtef64",
+        &[FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(Opcode::LTF64, 50, "ltf64", &[FloatRegister, FloatRegister]),
+    OpcodeInfo::new(
+        Opcode::LTEF64,
+        51,
+        "ltef64",
+        &[FloatRegister, FloatRegister],
+    ),
+    OpcodeInfo::new(Opcode::ITOF, 52, "itof", &[Register, FloatRegister]),
+    OpcodeInfo::new(Opcode::FTOI, 53, "ftoi", &[FloatRegister, Register]),
+    OpcodeInfo::new(Opcode::LOADW, 54, "loadw", &[Register, Word]),
+    OpcodeInfo::new(Opcode::PRTS, 55, "prts", &[Register]),
+    OpcodeInfo::new(Opcode::PRTSI, 56, "prts", &[Address]),
+    OpcodeInfo::new(Opcode::PRTI, 57, "prti", &[Register]),
+    OpcodeInfo::new(Opcode::READ, 58, "read", &[Register]),
+    OpcodeInfo::new(Opcode::SYSCALL, 59, "syscall", &[Immediate]),
+    OpcodeInfo::new(Opcode::HLT, 99, "hlt", &[]),
+    OpcodeInfo::new(Opcode::IGL, 100, "igl", &[]),
+];
+
+/// Decoded instruction operand.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    Register(u8),
+    FloatRegister(u8),
+    Immediate(i16),
+    Address(u16),
+    Word(i32),
+    Float(f64),
+}
+
+/// Decoded operands of a single instruction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Operands {
+    values: [Operand; MAX_OPERANDS],
+    len: usize,
+}
+
+impl Operands {
+    pub fn as_slice(&self) -> &[Operand] {
+        &self.values[..self.len]
+    }
+
+    /// Returns an index of the register operand at the given position.
+    ///
+    /// Operand kinds are guaranteed by the opcode table,
+    /// so asking for a wrong kind is a bug in the caller.
+    pub fn register(&self, i: usize) -> usize {
+        match self.values[i] {
+            Operand::Register(reg) | Operand::FloatRegister(reg) => reg as usize,
+            op => unreachable!("operand {:?} is not a register", op),
+        }
+    }
+
+    /// Returns a value of the 16-bit immediate operand at the given position.
+    pub fn immediate(&self, i: usize) -> i16 {
+        match self.values[i] {
+            Operand::Immediate(value) => value,
+            op => unreachable!("operand {:?} is not an immediate", op),
+        }
+    }
+
+    /// Returns a value of the address operand at the given position.
+    pub fn address(&self, i: usize) -> u16 {
+        match self.values[i] {
+            Operand::Address(value) => value,
+            op => unreachable!("operand {:?} is not an address", op),
+        }
+    }
+
+    /// Returns a value of the 32-bit immediate operand at the given position.
+    pub fn word(&self, i: usize) -> i32 {
+        match self.values[i] {
+            Operand::Word(value) => value,
+            op => unreachable!("operand {:?} is not a word", op),
+        }
+    }
+
+    /// Returns a value of the float operand at the given position.
+    pub fn float(&self, i: usize) -> f64 {
+        match self.values[i] {
+            Operand::Float(value) => value,
+            op => unreachable!("operand {:?} is not a float", op),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeError {
+    IllegalOpcode(u8),
+    Truncated,
+    InvalidRegister(u8),
+}
+
+impl Opcode {
+    /// Returns encoding metadata of the opcode.
+    pub fn info(&self) -> &'static OpcodeInfo {
+        OPCODES
+            .iter()
+            .find(|info| info.opcode == *self)
+            .expect("every opcode is present in the table")
+    }
+
+    pub fn mnemonic(&self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    /// Checks whether the address operand of the opcode
+    /// points into the read-only data rather than code.
+    pub fn reads_data(&self) -> bool {
+        *self == Opcode::PRTSI
+    }
+
+    /// Returns all opcodes sharing the given mnemonic.
+    pub fn overloads(mnemonic: &str) -> impl Iterator<Item = &'static OpcodeInfo> + '_ {
+        OPCODES.iter().filter(move |info| info.mnemonic == mnemonic)
+    }
+
+    /// Decodes operands of the opcode from `bytes`
+    /// which start right after the opcode byte.
+    pub fn decode_operands(&self, bytes: &[u8]) -> Result<Operands, DecodeError> {
+        let mut operands = Operands {
+            values: [Operand::Immediate(0); MAX_OPERANDS],
+            len: 0,
+        };
+        let mut pos = 0;
+        for kind in self.info().operands {
+            let raw = bytes
+                .get(pos..pos + kind.size())
+                .ok_or(DecodeError::Truncated)?;
+            let operand = match kind {
+                OperandKind::Register | OperandKind::FloatRegister => {
+                    if raw[0] as usize >= REGISTER_COUNT {
+                        return Err(DecodeError::InvalidRegister(raw[0]));
+                    }
+                    if *kind == OperandKind::Register {
+                        Operand::Register(raw[0])
+                    } else {
+                        Operand::FloatRegister(raw[0])
+                    }
+                }
+                OperandKind::Immediate => Operand::Immediate(i16::from_be_bytes([raw[0], raw[1]])),
+                OperandKind::Address => Operand::Address(u16::from_be_bytes([raw[0], raw[1]])),
+                OperandKind::Word => {
+                    Operand::Word(i32::from_be_bytes([raw[0], raw[1], raw[2], raw[3]]))
+                }
+                OperandKind::Float => {
+                    let mut float = [0; 8];
+                    float.copy_from_slice(raw);
+                    Operand::Float(f64::from_be_bytes(float))
+                }
+            };
+            operands.values[operands.len] = operand;
+            operands.len += 1;
+            pos += kind.size();
+        }
+        Ok(operands)
+    }
+}
+
+/// Decodes an instruction at the start of `bytes`.
+pub fn decode(bytes: &[u8]) -> Result<(Opcode, Operands), DecodeError> {
+    let code = *bytes.first().ok_or(DecodeError::Truncated)?;
+    let opcode = Opcode::from(code);
+    if opcode == Opcode::IGL {
+        return Err(DecodeError::IllegalOpcode(code));
+    }
+    let operands = opcode.decode_operands(&bytes[1..])?;
+    Ok((opcode, operands))
+}
+
 impl From<&str> for Opcode {
     fn from(source: &str) -> Self {
-        match source {
-            "nop" => Opcode::NOP,
-            "load" => Opcode::LOAD,
-            "alloc" => Opcode::ALLOC,
-            "add" => Opcode::ADD,
-            "sub" => Opcode::SUB,
-            "mul" => Opcode::MUL,
-            "div" => Opcode::DIV,
-            "jmp" => Opcode::JMP,
-            "jmpf" => Opcode::JMPF,
-            "jmpb" => Opcode::JMPB,
-            "eq" => Opcode::EQ,
-            "jeq" => Opcode::JEQ,
-            "jneq" => Opcode::JNEQ,
-            "inc" => Opcode::INC,
-            "dec" => Opcode::DEC,
-            "hlt" => Opcode::HLT,
-            _ => Opcode::IGL,
-        }
+        Opcode::overloads(source)
+            .next()
+            .map_or(Opcode::IGL, |info| info.opcode)
     }
 }
 
 impl From<u8> for Opcode {
     fn from(source: u8) -> Self {
-        match source {
-            0 => Opcode::NOP,
-            1 => Opcode::LOAD,
-            2 => Opcode::ALLOC,
-            3 => Opcode::ADD,
-            4 => Opcode::SUB,
-            5 => Opcode::MUL,
-            6 => Opcode::DIV,
-            7 => Opcode::JMP,
-            8 => Opcode::JMPF,
-            9 => Opcode::JMPB,
-            10 => Opcode::EQ,
-            11 => Opcode::JEQ,
-            12 => Opcode::JNEQ,
-            13 => Opcode::INC,
-            14 => Opcode::DEC,
-            99 => Opcode::HLT,
-            _ => Opcode::IGL,
-        }
+        OPCODES
+            .iter()
+            .find(|info| info.code == source)
+            .map_or(Opcode::IGL, |info| info.opcode)
     }
 }
 
 impl From<Opcode> for u8 {
     fn from(source: Opcode) -> Self {
-        match source {
-            Opcode::NOP => 0,
-            Opcode::LOAD => 1,
-            Opcode::ALLOC => 2,
-            Opcode::ADD => 3,
-            Opcode::SUB => 4,
-            Opcode::MUL => 5,
-            Opcode::DIV => 6,
-            Opcode::JMP => 7,
-            Opcode::JMPF => 8,
-            Opcode::JMPB => 9,
-            Opcode::EQ => 10,
-            Opcode::JEQ => 11,
-            Opcode::JNEQ => 12,
-            Opcode::INC => 13,
-            Opcode::DEC => 14,
-            Opcode::HLT => 99,
-            Opcode::IGL => 100,
-        }
+        source.info().code
     }
 }
 
@@ -113,6 +505,8 @@ mod tests {
     fn test_opcode_from_str() {
         let opcode = Opcode::from("load");
         assert_eq!(opcode, Opcode::LOAD);
+        let opcode = Opcode::from("gte");
+        assert_eq!(opcode, Opcode::GTE);
         let opcode = Opcode::from("illegal");
         assert_eq!(opcode, Opcode::IGL);
     }
@@ -122,4 +516,51 @@ mod tests {
         let opcode = Opcode::HLT;
         assert_eq!(opcode, Opcode::HLT);
     }
+
+    #[test]
+    fn test_opcode_table() {
+        for (i, info) in OPCODES.iter().enumerate() {
+            assert_eq!(Opcode::from(info.code), info.opcode);
+            assert_eq!(u8::from(info.opcode), info.code);
+            assert!(info.operands.len() <= MAX_OPERANDS);
+            for other in &OPCODES[i + 1..] {
+                assert_ne!(info.code, other.code);
+                assert_ne!(info.opcode, other.opcode);
+                if info.mnemonic == other.mnemonic {
+                    assert_ne!(info.operands, other.operands);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_overloads() {
+        let jumps: Vec<_> = Opcode::overloads("jmp").map(|info| info.opcode).collect();
+        assert_eq!(jumps, vec![Opcode::JMP, Opcode::JMPI]);
+        assert_eq!(Opcode::CALLI.mnemonic(), "call");
+        assert_eq!(Opcode::LOAD.info().instruction_len(), 4);
+        assert_eq!(Opcode::LOADF64.info().instruction_len(), 10);
+    }
+
+    #[test]
+    fn test_decode() {
+        let (opcode, operands) = decode(&[Opcode::LOAD.into(), 3, 1, 244]).unwrap();
+        assert_eq!(opcode, Opcode::LOAD);
+        assert_eq!(
+            operands.as_slice(),
+            &[Operand::Register(3), Operand::Immediate(500)]
+        );
+        assert_eq!(operands.register(0), 3);
+        assert_eq!(operands.immediate(1), 500);
+
+        assert_eq!(decode(&[200]), Err(DecodeError::IllegalOpcode(200)));
+        assert_eq!(
+            decode(&[Opcode::LOAD.into(), 3, 1]),
+            Err(DecodeError::Truncated)
+        );
+        assert_eq!(
+            decode(&[Opcode::INC.into(), 32]),
+            Err(DecodeError::InvalidRegister(32))
+        );
+    }
 }
@@ -0,0 +1,353 @@
+use crate::assembler::ENTRY_LABEL;
+use crate::bytecode::{
+    DebugInfo, Executable, LineEntry, Object, RelocationTarget, SectionKind, Symbol,
+};
+use crate::instruction::OperandKind;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkError {
+    /// Label exported by more than one object.
+    DuplicateSymbol(String),
+    /// Label imported by an object, but exported by none.
+    UndefinedSymbol(String),
+    /// Object exports a label it doesn't declare.
+    MissingExport(String),
+    /// Relocated operand lies outside of its section.
+    InvalidRelocation(u32),
+    /// Relocated value does not fit into its operand.
+    OutOfRange {
+        section: SectionKind,
+        offset: u32,
+        value: i64,
+    },
+}
+
+impl Display for LinkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkError::DuplicateSymbol(name) => {
+                write!(f, "Symbol exported by more than one object: {}", name)
+            }
+            LinkError::UndefinedSymbol(name) => write!(f, "Undefined symbol: {}", name),
+            LinkError::MissingExport(name) => {
+                write!(f, "Exported symbol is not declared: {}", name)
+            }
+            LinkError::InvalidRelocation(offset) => {
+                write!(f, "Relocation at {} lies outside of its section", offset)
+            }
+            LinkError::OutOfRange {
+                section,
+                offset,
+                value,
+            } => write!(
+                f,
+                "Relocated value {} does not fit into the operand at {:?} {}",
+                value, section, offset
+            ),
+        }
+    }
+}
+
+impl Error for LinkError {}
+
+/// Links objects into an executable.
+///
+/// Sections of the objects are placed one after another in the given
+/// order, then imported labels are resolved to the exported ones and
+/// relocated operands are adjusted by their targets' addresses.
+/// All errors are reported at once.
+///
+/// Line tables of the objects are merged, shifted by the offsets
+/// of their code. Only the exported labels make it into the
+/// executable's symbols.
+/// Execution starts at the exported `main` label, or at the `main`
+/// label of the first object, or at the first instruction.
+pub fn link(objects: &[Object]) -> Result<Executable, Vec<LinkError>> {
+    let mut errors = vec![];
+    let mut executable = Executable::default();
+    let mut bases = vec![];
+    for object in objects {
+        let base = |section: &[u8]| section.len() as u32;
+        bases.push((base(&executable.code), base(&executable.ro_data)));
+        executable.code.extend_from_slice(&object.code);
+        executable.ro_data.extend_from_slice(&object.ro_data);
+    }
+    for (object, (code_base, _)) in objects.iter().zip(bases.iter().copied()) {
+        merge_debug_info(&mut executable.debug_info, &object.debug_info, code_base);
+    }
+    let base_of = |(code, ro_data): (u32, u32), section| match section {
+        SectionKind::ReadOnlyData => ro_data,
+        _ => code,
+    };
+
+    let mut exports: HashMap<&str, Symbol> = HashMap::new();
+    for (object, bases) in objects.iter().zip(bases.iter().copied()) {
+        for name in &object.exports {
+            let symbol = match object.symbols.iter().find(|s| s.name == *name) {
+                Some(symbol) => symbol,
+                None => {
+                    errors.push(LinkError::MissingExport(name.clone()));
+                    continue;
+                }
+            };
+            if exports.contains_key(name.as_str()) {
+                errors.push(LinkError::DuplicateSymbol(name.clone()));
+                continue;
+            }
+            let symbol = Symbol {
+                name: name.clone(),
+                section: symbol.section,
+                offset: symbol.offset + base_of(bases, symbol.section),
+            };
+            exports.insert(name, symbol);
+        }
+    }
+
+    let mut undefined = vec![];
+    for name in objects.iter().flat_map(|object| &object.imports) {
+        if !exports.contains_key(name.as_str()) && !undefined.contains(&name) {
+            undefined.push(name);
+            errors.push(LinkError::UndefinedSymbol(name.clone()));
+        }
+    }
+
+    for (object, bases) in objects.iter().zip(bases.iter().copied()) {
+        for relocation in &object.relocations {
+            let address = match &relocation.target {
+                RelocationTarget::Section(section) => base_of(bases, *section),
+                RelocationTarget::Symbol(name) => match exports.get(name.as_str()) {
+                    Some(symbol) => symbol.offset,
+                    // Already reported as undefined
+                    None => continue,
+                },
+            };
+            let output = match relocation.section {
+                SectionKind::ReadOnlyData => &mut executable.ro_data,
+                _ => &mut executable.code,
+            };
+            let offset = relocation.offset + base_of(bases, relocation.section);
+            let start = offset as usize;
+            let operand = match output.get_mut(start..start + relocation.kind.size()) {
+                Some(operand) => operand,
+                None => {
+                    errors.push(LinkError::InvalidRelocation(relocation.offset));
+                    continue;
+                }
+            };
+            let value = read_operand(operand, relocation.kind) + i64::from(address);
+            match relocation.kind.range() {
+                Some((min, max)) if (min..=max).contains(&value) => {
+                    let bytes = value.to_be_bytes();
+                    operand.copy_from_slice(&bytes[bytes.len() - operand.len()..]);
+                }
+                _ => errors.push(LinkError::OutOfRange {
+                    section: relocation.section,
+                    offset,
+                    value,
+                }),
+            }
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let entry_point = exports
+        .get(ENTRY_LABEL)
+        .or_else(|| {
+            let symbols = objects.first().map_or(&[][..], |object| &object.symbols);
+            symbols.iter().find(|symbol| symbol.name == ENTRY_LABEL)
+        })
+        .filter(|symbol| symbol.section == SectionKind::Code)
+        .map_or(0, |symbol| symbol.offset);
+    let mut symbols: Vec<_> = exports.into_values().collect();
+    symbols.sort_by_key(|s| (u8::from(s.section), s.offset, s.name.clone()));
+    executable.entry_point = entry_point;
+    executable.symbols = symbols;
+    Ok(executable)
+}
+
+/// Appends the line table of an object placed at the code offset,
+/// sharing the files of the same name with other objects.
+fn merge_debug_info(output: &mut DebugInfo, debug_info: &DebugInfo, code_base: u32) {
+    let files: Vec<u32> = debug_info
+        .files
+        .iter()
+        .map(|name| {
+            // Unnamed sources of different objects are distinct
+            let existing = match name.is_empty() {
+                true => None,
+                false => output.files.iter().position(|file| file == name),
+            };
+            existing.unwrap_or_else(|| {
+                output.files.push(name.clone());
+                output.files.len() - 1
+            }) as u32
+        })
+        .collect();
+    for entry in &debug_info.lines {
+        if let Some(&file) = files.get(entry.file as usize) {
+            output.lines.push(LineEntry {
+                offset: entry.offset + code_base,
+                file,
+                ..*entry
+            });
+        }
+    }
+}
+
+/// Reads a big-endian integer operand of the given kind.
+fn read_operand(bytes: &[u8], kind: OperandKind) -> i64 {
+    match kind {
+        OperandKind::Immediate => i64::from(i16::from_be_bytes([bytes[0], bytes[1]])),
+        OperandKind::Address => i64::from(u16::from_be_bytes([bytes[0], bytes[1]])),
+        _ => i64::from(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::{ExitReason, VM};
+
+    fn object(source: &str) -> Object {
+        Assembler::new().assemble_object(source).unwrap()
+    }
+
+    #[test]
+    fn test_link() {
+        let lib = object(
+            "
+        .export double greeting
+        .data
+        greeting: .asciiz \"Hi\"
+        .code
+        double: add $0 $0 $0
+            ret
+        ",
+        );
+        let program = object(
+            "
+        .import double greeting
+        .data
+        pointers: .integer @greeting @end
+        .code
+        main: load $0 #21
+            call @double
+            load $1 @greeting+1
+            jmp @end
+            hlt
+        end: hlt
+        ",
+        );
+        assert_eq!(program.imports, vec!["double", "greeting"]);
+        assert_eq!(program.relocations.len(), 5);
+
+        let mut lib = lib;
+        let mut extra = object("nop\n");
+        lib.debug_info.set_main_file("lib.iasm");
+        extra.debug_info.set_main_file("lib.iasm");
+        let executable = link(&[program, lib, extra]).unwrap();
+        let debug_info = &executable.debug_info;
+        assert_eq!(debug_info.files, vec!["", "lib.iasm"]);
+        assert_eq!(debug_info.format_location(0).unwrap(), "<input>:6:9");
+        assert_eq!(debug_info.format_location(16).unwrap(), "lib.iasm:6:9");
+        assert_eq!(debug_info.format_location(21).unwrap(), "lib.iasm:1:1");
+        assert_eq!(executable.entry_point, 0);
+        assert_eq!(executable.ro_data[..8], [0, 0, 0, 8, 0, 0, 0, 15]);
+        assert_eq!(
+            executable.symbols,
+            vec![
+                Symbol {
+                    name: "double".to_string(),
+                    section: SectionKind::Code,
+                    offset: 16
+                },
+                Symbol {
+                    name: "greeting".to_string(),
+                    section: SectionKind::ReadOnlyData,
+                    offset: 8
+                },
+            ]
+        );
+
+        let mut vm = VM::new();
+        vm.load_executable(executable);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[..2], [42, 9]);
+    }
+
+    #[test]
+    fn test_link_errors() {
+        let a = object(".export f\n.import g h\nf: call @g\ncall @h\nhlt\n");
+        let b = object(".export f\nf: hlt\n");
+        let errors = link(&[a.clone(), b]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::DuplicateSymbol("f".to_string()),
+                LinkError::UndefinedSymbol("g".to_string()),
+                LinkError::UndefinedSymbol("h".to_string()),
+            ]
+        );
+
+        let mut far = Object {
+            code: vec![0; 0x10000],
+            ..Object::default()
+        };
+        far.symbols.push(Symbol {
+            name: "g".to_string(),
+            section: SectionKind::Code,
+            offset: 0xFFFF,
+        });
+        far.exports = vec!["g".to_string(), "h".to_string(), "missing".to_string()];
+        far.symbols.push(Symbol {
+            name: "h".to_string(),
+            section: SectionKind::Code,
+            offset: 0,
+        });
+        let errors = link(&[a, far]).unwrap_err();
+        assert_eq!(
+            errors,
+            vec![
+                LinkError::MissingExport("missing".to_string()),
+                LinkError::OutOfRange {
+                    section: SectionKind::Code,
+                    offset: 1,
+                    value: 0x10006
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_assemble_object_errors() {
+        let diagnostics = Assembler::new()
+            .assemble(".import f\ncall @f\n")
+            .unwrap_err();
+        assert!(diagnostics
+            .to_string()
+            .contains("only be resolved by the linker"));
+
+        let diagnostics = Assembler::new()
+            .assemble_object(
+                ".equ A @x\nx: load $0 #(@x * 2)\n.export y\n.import x\n.data\n.bytes @x\n",
+            )
+            .unwrap_err();
+        let errors: Vec<_> = diagnostics.iter().map(|d| d.error.to_string()).collect();
+        assert_eq!(
+            errors,
+            vec![
+                "Invalid operand: Unable to relocate: @x",
+                "Invalid operand: Unable to relocate: (x * 2)",
+                "Invalid operand: Undefined label: y",
+                "Label declared more than once: x",
+                "Invalid operand: Unable to relocate: @x",
+            ]
+        );
+    }
+}
@@ -1,13 +1,21 @@
 pub mod assembler;
+pub mod bytecode;
+pub mod cli;
+pub mod dap;
+pub mod debugger;
+pub mod disassembler;
 pub mod instruction;
+pub mod linker;
 pub mod repl;
+pub mod stdlib;
 pub mod vm;
 
-use repl::REPL;
-use vm::VM;
+use std::process;
 
 fn main() {
-    let vm = VM::new();
-    let mut repl = REPL::new(vm);
-    repl.run();
+    let result = cli::parse_args(std::env::args().skip(1)).and_then(|command| command.execute());
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(e.exit_code());
+    }
 }
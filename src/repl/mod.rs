@@ -1,9 +1,16 @@
-use crate::assembler::parsing::program;
-use crate::vm::VM;
+use crate::assembler::Assembler;
+use crate::bytecode::SectionKind;
+use crate::cli::load_executable;
+use crate::debugger::{Debugger, StopReason, Watchpoint};
+use crate::disassembler::{disassemble_program, format_instruction};
+use crate::instruction::decode;
+use crate::vm::{ExitReason, VmError, VM};
+use std::collections::HashMap;
 use std::io::{self, Write};
+use std::path::Path;
 
 pub struct REPL {
-    vm: VM,
+    debugger: Debugger,
     command_buffer: Vec<String>,
 }
 
@@ -11,7 +18,7 @@ impl REPL {
     /// Creates a new REPL.
     pub fn new(vm: VM) -> REPL {
         REPL {
-            vm,
+            debugger: Debugger::new(vm),
             command_buffer: vec![],
         }
     }
@@ -29,21 +36,23 @@ impl REPL {
             print!(">>> ");
             stdout.flush().expect("Unable to flush STDOUT");
 
-            stdin
+            let read = stdin
                 .read_line(&mut buffer)
                 .expect("Unable to read from STDIN");
+            if read == 0 {
+                println!();
+                std::process::exit(0);
+            }
 
             let cmd = buffer.trim();
             self.command_buffer.push(cmd.to_string());
 
             match cmd {
                 ".program" => {
-                    for instr in &self.vm.program {
-                        println!("{}", instr);
-                    }
+                    print!("{}", disassemble_program(&self.debugger.vm().program));
                 }
                 ".registers" => {
-                    println!("{:#?}", self.vm.registers);
+                    println!("{:#?}", self.debugger.vm().registers);
                 }
                 ".history" => {
                     for cmd in &self.command_buffer {
@@ -54,18 +63,229 @@ impl REPL {
                     println!("Bye");
                     std::process::exit(0);
                 }
+                s if s.starts_with('.') => {
+                    let mut words = s[1..].split_whitespace();
+                    let command = words.next().unwrap_or_default();
+                    let args: Vec<_> = words.collect();
+                    match self.debug(command, &args) {
+                        Ok(text) => println!("{}", text),
+                        Err(e) => println!("{}", e),
+                    }
+                }
                 s => {
-                    let program = match program(s) {
-                        Ok((_, p)) => p,
-                        Err(_) => {
-                            println!("Unable to parse input");
+                    let executable = match Assembler::new().assemble(s) {
+                        Ok(executable) => executable,
+                        Err(diagnostics) => {
+                            println!("{}", diagnostics);
                             continue;
                         }
                     };
-                    self.vm.add_bytes(program.to_bytes());
-                    self.vm.step();
+                    self.debugger.vm_mut().add_bytes(executable.code);
+                    match self.debugger.vm_mut().step() {
+                        Ok(Some(ExitReason::Halted)) => println!("HLT encountered, stopping VM"),
+                        Ok(_) => {}
+                        Err(e) => println!("VM error: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Executes a debugger command, returning its output.
+    ///
+    /// Breakpoints are given by addresses, labels or `:line`
+    /// numbers of the loaded program, watchpoints by registers or heap addresses
+    /// with an optional number of bytes.
+    fn debug(&mut self, command: &str, args: &[&str]) -> Result<String, String> {
+        match (command, args) {
+            ("load", [path]) => {
+                let executable = load_executable(Path::new(path)).map_err(|e| e.to_string())?;
+                self.debugger.load(executable);
+                Ok(format!("Loaded {}\n{}", path, self.location()))
+            }
+            ("break", [location]) => {
+                let pc = self.address(location)?;
+                self.debugger.add_breakpoint(pc);
+                Ok(format!("Breakpoint at {}", pc))
+            }
+            ("delete", [location]) => {
+                match self.debugger.remove_breakpoint(self.address(location)?) {
+                    true => Ok("Breakpoint deleted".to_string()),
+                    false => Err(format!("No breakpoint at {}", location)),
                 }
             }
+            ("watch", args) => {
+                let watchpoint = watchpoint(args)?;
+                self.debugger
+                    .add_watchpoint(watchpoint)
+                    .map_err(|e| e.to_string())?;
+                Ok(format!("Watching {}", watchpoint))
+            }
+            ("unwatch", args) => match self.debugger.remove_watchpoint(watchpoint(args)?) {
+                true => Ok("Watchpoint deleted".to_string()),
+                false => Err(format!("Not watching {}", args.join(" "))),
+            },
+            ("breakpoints", []) => {
+                let breakpoints: Vec<_> = self
+                    .debugger
+                    .breakpoints()
+                    .map(|pc| pc.to_string())
+                    .collect();
+                let watchpoints: Vec<_> = self
+                    .debugger
+                    .watchpoints()
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect();
+                Ok(format!(
+                    "Breakpoints: {}\nWatchpoints: {}",
+                    breakpoints.join(" "),
+                    watchpoints.join(" ")
+                ))
+            }
+            ("step", []) => self.stopped(Debugger::step_into),
+            ("next", []) => self.stopped(Debugger::step_over),
+            ("continue", []) => self.stopped(Debugger::resume),
+            _ => Err(format!("Unknown command: .{} {}", command, args.join(" "))),
+        }
+    }
+
+    /// Runs the debugger and describes where it has stopped.
+    fn stopped(
+        &mut self,
+        run: impl Fn(&mut Debugger) -> Result<StopReason, VmError>,
+    ) -> Result<String, String> {
+        let reason = run(&mut self.debugger).map_err(|e| {
+            match self.debugger.vm().source_location(e.pc()) {
+                Some(location) => format!("VM error: {} ({})", e, location),
+                None => format!("VM error: {}", e),
+            }
+        })?;
+        Ok(format!("{}\n{}", reason, self.location()))
+    }
+
+    /// Describes the next instruction to execute.
+    fn location(&self) -> String {
+        let vm = self.debugger.vm();
+        let (opcode, operands) = match vm.program.get(vm.pc()..).map(decode) {
+            Some(Ok(instruction)) => instruction,
+            _ => return format!("{}: end of program", vm.pc()),
+        };
+        // Name address operands after the labels of the loaded program
+        let labels: HashMap<_, _> = self
+            .debugger
+            .symbols()
+            .iter()
+            .filter(|symbol| (symbol.section == SectionKind::ReadOnlyData) == opcode.reads_data())
+            .map(|symbol| (symbol.offset, symbol.name.clone()))
+            .collect();
+        let text = format!(
+            "{}: {}",
+            vm.pc(),
+            format_instruction(opcode, &operands, &labels)
+        );
+        match vm.source_location(vm.pc()) {
+            Some(location) => format!("{} ({})", text, location),
+            None => text,
+        }
+    }
+
+    /// Parses a code address, a label or a `:line` of the source.
+    fn address(&self, location: &str) -> Result<usize, String> {
+        if let Some(line) = location.strip_prefix(':') {
+            let line = line
+                .parse()
+                .map_err(|_| format!("Expected a line number, found: {}", line))?;
+            return self.debugger.line_address(line).map_err(|e| e.to_string());
+        }
+        match location.parse() {
+            Ok(pc) => Ok(pc),
+            Err(_) => self
+                .debugger
+                .label_address(location)
+                .map_err(|e| e.to_string()),
         }
     }
 }
+
+/// Parses a watchpoint: `$n` for a register, or a heap address
+/// followed by an optional number of bytes.
+fn watchpoint(args: &[&str]) -> Result<Watchpoint, String> {
+    let number = |arg: &str| {
+        arg.parse()
+            .map_err(|_| format!("Expected a number, found: {}", arg))
+    };
+    match args {
+        [register] if register.starts_with('$') => {
+            Ok(Watchpoint::Register(number(&register[1..])?))
+        }
+        [address] => Ok(Watchpoint::Heap {
+            address: number(address)?,
+            len: 1,
+        }),
+        [address, len] => Ok(Watchpoint::Heap {
+            address: number(address)?,
+            len: number(len)?,
+        }),
+        _ => Err("Expected a register or a heap address and length".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_debug_commands() {
+        let mut repl = REPL::new(VM::new());
+        let executable = Assembler::new()
+            .assemble("main: load $0 #3\nloop: dec $0\ncall @noop\njmp @loop\nnoop: ret\n")
+            .unwrap();
+        repl.debugger.load(executable);
+        let mut debug = |line: &str| {
+            let words: Vec<_> = line.split_whitespace().collect();
+            repl.debug(words[0], &words[1..])
+        };
+        assert_eq!(debug("break loop"), Ok("Breakpoint at 4".to_string()));
+        assert_eq!(debug("watch $0"), Ok("Watching $0".to_string()));
+        assert_eq!(
+            debug("continue"),
+            Ok("Watchpoint $0 changed: 0 -> 3\n4: dec $0 (<input>:2:1)".to_string())
+        );
+        assert_eq!(
+            debug("step"),
+            Ok("Watchpoint $0 changed: 3 -> 2\n6: call @noop (<input>:3:1)".to_string())
+        );
+        assert_eq!(
+            debug("next"),
+            Ok("Step completed\n9: jmp @loop (<input>:4:1)".to_string())
+        );
+        assert_eq!(
+            debug("continue"),
+            Ok("Breakpoint at 4\n4: dec $0 (<input>:2:1)".to_string())
+        );
+        assert_eq!(debug("unwatch $0"), Ok("Watchpoint deleted".to_string()));
+        assert_eq!(debug("watch 16 4"), Ok("Watching heap[16..20]".to_string()));
+        assert_eq!(
+            debug("breakpoints"),
+            Ok("Breakpoints: 4\nWatchpoints: heap[16..20]".to_string())
+        );
+        assert_eq!(debug("delete :2"), Ok("Breakpoint deleted".to_string()));
+        assert_eq!(debug("delete 4"), Err("No breakpoint at 4".to_string()));
+        assert_eq!(
+            debug("break nope"),
+            Err("Undefined label: nope".to_string())
+        );
+        assert_eq!(debug("break :5"), Ok("Breakpoint at 12".to_string()));
+        assert_eq!(
+            debug("break :6"),
+            Err("No code at or below line 6".to_string())
+        );
+        assert_eq!(
+            debug("watch $x"),
+            Err("Expected a number, found: x".to_string())
+        );
+        assert!(debug("load /nonexistent.iasm").is_err());
+        assert!(debug("frobnicate").is_err());
+    }
+}
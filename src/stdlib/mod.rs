@@ -0,0 +1,265 @@
+//! Standard library of assembly routines shipped with the VM.
+//!
+//! Every module is assembled by the build script into an object file,
+//! which is embedded into the binary and linked into the programs
+//! importing its routines. A module that doesn't assemble fails the build.
+//!
+//! Routines take their arguments in `$0`, `$1`, `$2` and return
+//! the result in `$0`, preserving all the other registers.
+//! The comparison flag is not preserved.
+
+use crate::bytecode::{BytecodeError, Object};
+use std::sync::OnceLock;
+
+/// Names and object files of the library modules.
+pub const MODULES: [(&str, &[u8]); 5] = [
+    ("mem", include_bytes!(concat!(env!("OUT_DIR"), "/mem.iob"))),
+    (
+        "alloc",
+        include_bytes!(concat!(env!("OUT_DIR"), "/alloc.iob")),
+    ),
+    (
+        "string",
+        include_bytes!(concat!(env!("OUT_DIR"), "/string.iob")),
+    ),
+    (
+        "math",
+        include_bytes!(concat!(env!("OUT_DIR"), "/math.iob")),
+    ),
+    ("io", include_bytes!(concat!(env!("OUT_DIR"), "/io.iob"))),
+];
+
+/// Parsed library modules, in the order of `MODULES`.
+static OBJECTS: OnceLock<Result<Vec<Object>, BytecodeError>> = OnceLock::new();
+
+/// Returns all the library modules, parsing them on the first call.
+pub fn modules() -> Result<&'static [Object], BytecodeError> {
+    let objects = OBJECTS.get_or_init(|| {
+        MODULES
+            .iter()
+            .map(|(_, bytes)| Object::from_bytes(bytes))
+            .collect()
+    });
+    objects.as_deref().map_err(Clone::clone)
+}
+
+/// Returns the library modules needed to resolve the imports
+/// the given objects don't export themselves.
+///
+/// Modules are pulled in as the linker would look them up in an archive:
+/// only the ones exporting a missing label are added, along with
+/// the modules they need in turn. Labels left unresolved are
+/// up to the linker to report.
+pub fn required(objects: &[Object]) -> Result<Vec<Object>, BytecodeError> {
+    let mut available = modules()?.to_vec();
+    let mut required: Vec<Object> = vec![];
+    loop {
+        let linked = || objects.iter().chain(required.iter());
+        let exported = |name: &String| linked().any(|object| object.exports.contains(name));
+        let missing: Vec<_> = linked()
+            .flat_map(|object| &object.imports)
+            .filter(|name| !exported(name))
+            .cloned()
+            .collect();
+        let position = available
+            .iter()
+            .position(|module| module.exports.iter().any(|name| missing.contains(name)));
+        match position {
+            Some(i) => required.push(available.remove(i)),
+            None => return Ok(required),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::linker::link;
+    use crate::vm::{ExitReason, OutputBuffer, VM};
+
+    /// Links a program against the library and runs it.
+    fn run(source: &str) -> VM {
+        run_with(VM::new(), source)
+    }
+
+    fn run_with(mut vm: VM, source: &str) -> VM {
+        let program = Assembler::new().assemble_object(source).unwrap();
+        let mut objects = vec![program];
+        objects.extend(required(&objects).unwrap());
+        vm.load_executable(link(&objects).unwrap());
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.sp(), 0);
+        vm
+    }
+
+    #[test]
+    fn test_modules() {
+        let modules = modules().unwrap();
+        assert_eq!(modules.len(), MODULES.len());
+        for (object, (name, _)) in modules.iter().zip(MODULES.iter()) {
+            assert!(!object.exports.is_empty(), "{}", name);
+            assert_eq!(object.debug_info.files[0], format!("stdlib/{}.iasm", name));
+        }
+    }
+
+    #[test]
+    fn test_required() {
+        let program = Assembler::new()
+            .assemble_object(".import realloc abs\nhlt\n")
+            .unwrap();
+        let exports: Vec<_> = required(&[program])
+            .unwrap()
+            .into_iter()
+            .map(|module| module.exports[0].clone())
+            .collect();
+        assert_eq!(exports, vec!["heap_init", "memcpy", "abs"]);
+        assert!(required(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_alloc_and_mem() {
+        let vm = run("
+        .import heap_init malloc realloc memset memcpy
+        main: call @heap_init
+            load $0 #3
+            call @malloc
+            push $0
+            pop $10
+            load $1 #'x'
+            load $2 #3
+            call @memset
+            load $1 #5
+            call @realloc
+            push $0
+            pop $11
+            load $0 #2
+            call @malloc
+            push $0
+            pop $12
+            push $11
+            pop $1
+            load $2 #2
+            call @memcpy
+            hlt
+        ");
+        assert_eq!(vm.registers[10..13], [8, 15, 24]);
+        assert_eq!(vm.registers[1..3], [15, 2]);
+        assert_eq!(vm.heap()[..4], [0, 0, 0, 26]);
+        assert_eq!(
+            vm.heap()[4..],
+            *b"\0\0\0\x03xxx\0\0\0\x05xxx\0\0\0\0\0\x02xx"
+        );
+    }
+
+    #[test]
+    fn test_string() {
+        let vm = run("
+        .import heap_init malloc itoa strlen
+        main: call @heap_init
+            load $0 #32
+            call @malloc
+            push $0
+            pop $1
+            loadw $0 #-2147483648
+            call @itoa
+            push $0
+            pop $20
+            push $1
+            pop $0
+            call @strlen
+            push $0
+            pop $21
+            load $0 #0
+            load $1 #20
+            call @itoa
+            push $0
+            pop $22
+            load $0 #907
+            call @itoa
+            push $0
+            pop $23
+            hlt
+        ");
+        assert_eq!(vm.registers[20..24], [11, 11, 1, 3]);
+        assert_eq!(vm.heap()[8..24], *b"-2147483648\0907\0");
+    }
+
+    #[test]
+    fn test_math() {
+        let vm = run("
+        .import abs min max rem pow gcd
+        main: load $0 #-7
+            call @abs
+            push $0
+            pop $20
+            load $1 #3
+            call @min
+            push $0
+            pop $21
+            call @max
+            push $0
+            pop $22
+            load $0 #-7
+            call @rem
+            push $0
+            pop $23
+            load $0 #3
+            load $1 #4
+            call @pow
+            push $0
+            pop $24
+            load $0 #-84
+            load $1 #36
+            call @gcd
+            push $0
+            pop $25
+            hlt
+        ");
+        assert_eq!(vm.registers[20..26], [7, 3, 3, -1, 81, 12]);
+        assert_eq!(vm.registers[1], 36);
+    }
+
+    #[test]
+    fn test_io() {
+        let output = OutputBuffer::new();
+        let vm = VM::new()
+            .with_output(output.clone())
+            .with_input(&b"Hello, world\nabc"[..]);
+        let vm = run_with(
+            vm,
+            "
+        .import heap_init malloc itoa puts read_line
+        main: call @heap_init
+            load $0 #8
+            call @malloc
+            push $0
+            pop $10
+            load $1 #6
+            call @read_line
+            prti $0
+            push $10
+            pop $0
+            call @puts
+            load $1 #16
+            call @read_line
+            push $10
+            pop $0
+            call @read_line
+            push $0
+            pop $20
+            push $10
+            pop $0
+            call @read_line
+            push $0
+            pop $21
+            push $10
+            pop $0
+            call @puts
+            hlt
+        ",
+        );
+        assert_eq!(vm.registers[20..22], [3, -1]);
+        assert_eq!(output.text(), "5Hello\n\n");
+    }
+}
@@ -0,0 +1,58 @@
+use std::cell::RefCell;
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+
+/// Destination of the program output, stdout by default.
+pub(super) struct Output(pub(super) Box<dyn Write>);
+
+impl Default for Output {
+    fn default() -> Self {
+        Output(Box::new(io::stdout()))
+    }
+}
+
+/// Source of the program input, stdin by default.
+pub(super) struct Input(pub(super) Box<dyn Read>);
+
+impl Default for Input {
+    fn default() -> Self {
+        Input(Box::new(io::stdin()))
+    }
+}
+
+/// In-memory output shared between the VM and its owner,
+/// to inspect what a program has printed.
+#[derive(Debug, Clone, Default)]
+pub struct OutputBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl OutputBuffer {
+    pub fn new() -> OutputBuffer {
+        OutputBuffer::default()
+    }
+
+    /// Returns the bytes printed so far.
+    pub fn contents(&self) -> Vec<u8> {
+        self.0.borrow().clone()
+    }
+
+    /// Returns the output printed so far, replacing invalid UTF-8.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.borrow()).into_owned()
+    }
+
+    /// Removes and returns the bytes printed so far.
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+impl Write for OutputBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,155 @@
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::io;
+
+/// Reason why the VM stopped executing a program.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExitReason {
+    /// `HLT` instruction has been executed.
+    Halted,
+    /// Program counter has reached the end of the program.
+    EndOfProgram,
+}
+
+/// Fault raised by the VM while executing a program.
+///
+/// Every variant carries the address of the instruction that caused it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VmError {
+    IllegalOpcode {
+        opcode: u8,
+        pc: usize,
+    },
+    InvalidRegister {
+        register: usize,
+        pc: usize,
+    },
+    DivisionByZero {
+        pc: usize,
+    },
+    TruncatedInstruction {
+        pc: usize,
+    },
+    InvalidAllocation {
+        size: i32,
+        pc: usize,
+    },
+    HeapOverflow {
+        requested: usize,
+        limit: usize,
+        pc: usize,
+    },
+    InvalidJump {
+        target: i64,
+        pc: usize,
+    },
+    HeapOutOfBounds {
+        address: i64,
+        size: usize,
+        pc: usize,
+    },
+    StackOverflow {
+        pc: usize,
+    },
+    StackUnderflow {
+        pc: usize,
+    },
+    /// No NUL byte between the address and the end of memory.
+    UnterminatedString {
+        address: i64,
+        pc: usize,
+    },
+    /// No host function is registered under the number.
+    UnknownSyscall {
+        number: u16,
+        pc: usize,
+    },
+    /// Host function has raised an error.
+    Host {
+        number: u16,
+        message: String,
+        pc: usize,
+    },
+    /// Reading input or writing output has failed.
+    Io {
+        kind: io::ErrorKind,
+        pc: usize,
+    },
+}
+
+impl VmError {
+    /// Returns an address of the faulting instruction.
+    pub fn pc(&self) -> usize {
+        match self {
+            VmError::IllegalOpcode { pc, .. }
+            | VmError::InvalidRegister { pc, .. }
+            | VmError::DivisionByZero { pc }
+            | VmError::TruncatedInstruction { pc }
+            | VmError::InvalidAllocation { pc, .. }
+            | VmError::HeapOverflow { pc, .. }
+            | VmError::InvalidJump { pc, .. }
+            | VmError::HeapOutOfBounds { pc, .. }
+            | VmError::StackOverflow { pc }
+            | VmError::StackUnderflow { pc }
+            | VmError::UnterminatedString { pc, .. }
+            | VmError::UnknownSyscall { pc, .. }
+            | VmError::Host { pc, .. }
+            | VmError::Io { pc, .. } => *pc,
+        }
+    }
+}
+
+impl Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            VmError::IllegalOpcode { opcode, pc } => {
+                write!(f, "Illegal opcode {} at {}", opcode, pc)
+            }
+            VmError::InvalidRegister { register, pc } => {
+                write!(f, "Invalid register ${} at {}", register, pc)
+            }
+            VmError::DivisionByZero { pc } => {
+                write!(f, "Division by zero at {}", pc)
+            }
+            VmError::TruncatedInstruction { pc } => {
+                write!(f, "Truncated instruction at {}", pc)
+            }
+            VmError::InvalidAllocation { size, pc } => {
+                write!(f, "Invalid allocation of {} bytes at {}", size, pc)
+            }
+            VmError::HeapOverflow {
+                requested,
+                limit,
+                pc,
+            } => write!(
+                f,
+                "Heap overflow at {}: {} bytes requested, limit is {}",
+                pc, requested, limit
+            ),
+            VmError::InvalidJump { target, pc } => {
+                write!(f, "Invalid jump target {} at {}", target, pc)
+            }
+            VmError::HeapOutOfBounds { address, size, pc } => write!(
+                f,
+                "Heap access of {} bytes at address {} is out of bounds at {}",
+                size, address, pc
+            ),
+            VmError::StackOverflow { pc } => write!(f, "Stack overflow at {}", pc),
+            VmError::StackUnderflow { pc } => write!(f, "Stack underflow at {}", pc),
+            VmError::UnterminatedString { address, pc } => {
+                write!(f, "Unterminated string at address {} at {}", address, pc)
+            }
+            VmError::UnknownSyscall { number, pc } => {
+                write!(f, "Unknown syscall {} at {}", number, pc)
+            }
+            VmError::Host {
+                number,
+                message,
+                pc,
+            } => write!(f, "Syscall {} failed at {}: {}", number, pc, message),
+            VmError::Io { kind, pc } => write!(f, "I/O error at {}: {}", pc, kind),
+        }
+    }
+}
+
+impl Error for VmError {}
@@ -0,0 +1,221 @@
+use crate::vm::HEAP_LIMIT;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::ops::Range;
+
+/// Function of the embedding application, called by `SYSCALL`.
+///
+/// Arguments and results are passed in registers, following
+/// the same convention as the stdlib: arguments in `$0`, `$1`, ...
+/// and the result in `$0`.
+pub trait HostFunction {
+    fn call(&mut self, context: &mut HostContext) -> Result<(), HostError>;
+}
+
+impl<F> HostFunction for F
+where
+    F: FnMut(&mut HostContext) -> Result<(), HostError>,
+{
+    fn call(&mut self, context: &mut HostContext) -> Result<(), HostError> {
+        self(context)
+    }
+}
+
+/// Part of the VM state a host function has access to.
+pub struct HostContext<'a> {
+    pub registers: &'a mut [i32; 32],
+    pub float_registers: &'a mut [f64; 32],
+    pub(super) heap: &'a mut Vec<u8>,
+    /// Heap ranges handed out for writing, for the tracer.
+    pub(super) writes: &'a mut Vec<Range<usize>>,
+}
+
+impl HostContext<'_> {
+    pub fn heap(&self) -> &[u8] {
+        self.heap
+    }
+
+    /// Returns a range of the heap to write to.
+    pub fn heap_mut(&mut self, range: Range<usize>) -> Result<&mut [u8], HostError> {
+        if range.start > range.end || range.end > self.heap.len() {
+            return Err(HostError::new(format!(
+                "Heap range {}..{} is out of bounds",
+                range.start, range.end
+            )));
+        }
+        self.writes.push(range.clone());
+        Ok(&mut self.heap[range])
+    }
+
+    /// Grows the heap like `ALLOC` does,
+    /// returning the address of the new bytes.
+    pub fn alloc(&mut self, bytes: usize) -> Result<usize, HostError> {
+        let address = self.heap.len();
+        let size = match address.checked_add(bytes) {
+            Some(size) if size <= HEAP_LIMIT => size,
+            _ => {
+                return Err(HostError::new(format!(
+                    "Heap overflow: {} more bytes requested, limit is {}",
+                    bytes, HEAP_LIMIT
+                )))
+            }
+        };
+        self.heap.resize(size, 0);
+        Ok(address)
+    }
+
+    /// Returns a NUL-terminated string from the heap.
+    pub fn string(&self, address: i32) -> Result<&[u8], HostError> {
+        let bytes = match address {
+            address if address >= 0 => self.heap.get(address as usize..),
+            _ => None,
+        };
+        bytes
+            .and_then(|bytes| Some(&bytes[..bytes.iter().position(|byte| *byte == 0)?]))
+            .ok_or_else(|| HostError::new(format!("Unterminated string at address {}", address)))
+    }
+}
+
+/// Error raised by a host function, stops the program.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HostError {
+    message: String,
+}
+
+impl HostError {
+    pub fn new(message: impl Into<String>) -> HostError {
+        HostError {
+            message: message.into(),
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for HostError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::{ExitReason, VmError, VM};
+
+    /// Host function keeping state between calls.
+    struct Counter(i32);
+
+    impl HostFunction for Counter {
+        fn call(&mut self, context: &mut HostContext) -> Result<(), HostError> {
+            self.0 += context.registers[0];
+            context.registers[0] = self.0;
+            Ok(())
+        }
+    }
+
+    fn load(vm: VM, source: &str) -> VM {
+        let mut vm = vm;
+        vm.load_executable(Assembler::new().assemble(source).unwrap());
+        vm
+    }
+
+    #[test]
+    fn test_syscall() {
+        let upper = |context: &mut HostContext| {
+            let string = context.string(context.registers[0])?.to_ascii_uppercase();
+            let address = context.alloc(string.len() + 1)?;
+            context
+                .heap_mut(address..address + string.len())?
+                .copy_from_slice(&string);
+            context.registers[0] = address as i32;
+            Ok(())
+        };
+        let vm = VM::new().with_syscall(1, Counter(0)).with_syscall(2, upper);
+        let mut vm = load(
+            vm,
+            "
+            load $0 #5
+            syscall #1
+            syscall #1
+            push $0
+            pop $1
+            load $0 #3
+            alloc $0
+            load $0 #'h'
+            load $2 #0
+            stb $0 $2 #0
+            load $0 #'i'
+            stb $0 $2 #1
+            load $0 #0
+            syscall #2
+            hlt
+            ",
+        );
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[..2], [3, 10]);
+        assert_eq!(vm.heap(), b"hi\0HI\0");
+    }
+
+    #[test]
+    fn test_syscall_errors() {
+        let fail = |_: &mut HostContext| Err(HostError::new("Service unavailable"));
+        let mut vm = load(VM::new().with_syscall(7, fail), "nop\nsyscall #7\n");
+        let error = vm.run().unwrap_err();
+        assert_eq!(
+            error,
+            VmError::Host {
+                number: 7,
+                message: "Service unavailable".to_string(),
+                pc: 1
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Syscall 7 failed at 1: Service unavailable"
+        );
+
+        let mut vm = load(VM::new(), "syscall #-1\n");
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnknownSyscall {
+                number: 0xFFFF,
+                pc: 0
+            })
+        );
+
+        let write = |context: &mut HostContext| context.heap_mut(0..1).map(|_| ());
+        let mut vm = load(VM::new().with_syscall(0, write), "syscall #0\n");
+        assert_eq!(
+            vm.run().unwrap_err().to_string(),
+            "Syscall 0 failed at 0: Heap range 0..1 is out of bounds"
+        );
+
+        let string = |context: &mut HostContext| context.string(0).map(|_| ());
+        let mut vm = load(VM::new().with_syscall(0, string), "syscall #0\n");
+        assert_eq!(
+            vm.run().unwrap_err().to_string(),
+            "Syscall 0 failed at 0: Unterminated string at address 0"
+        );
+
+        for bytes in [HEAP_LIMIT, usize::MAX] {
+            let alloc = move |context: &mut HostContext| context.alloc(bytes).map(|_| ());
+            let mut vm = load(
+                VM::new().with_syscall(0, alloc),
+                "load $0 #1\nalloc $0\nsyscall #0\n",
+            );
+            assert_eq!(
+                vm.run().unwrap_err().to_string(),
+                format!(
+                    "Syscall 0 failed at 6: Heap overflow: {} more bytes requested, limit is {}",
+                    bytes, HEAP_LIMIT
+                )
+            );
+        }
+    }
+}
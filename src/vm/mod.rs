@@ -0,0 +1,1202 @@
+mod console;
+mod error;
+mod host;
+mod trace;
+
+pub use console::OutputBuffer;
+pub use error::{ExitReason, VmError};
+pub use host::{HostContext, HostError, HostFunction};
+pub use trace::{HeapWrite, JsonTracer, RegisterChange, TextTracer, TraceStep, Tracer};
+
+use crate::bytecode::{BytecodeError, DebugInfo, Executable};
+use crate::instruction::{decode, DecodeError, Opcode, Operands};
+use console::{Input, Output};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::ops::Range;
+
+/// Maximum number of bytes a program can allocate on the heap.
+pub const HEAP_LIMIT: usize = 64 * 1024 * 1024;
+
+/// Maximum number of values the stack can hold.
+pub const STACK_LIMIT: usize = 64 * 1024;
+
+/// Virtual machine state.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct VM {
+    /// VM registers.
+    pub registers: [i32; 32],
+    /// Float registers.
+    pub float_registers: [f64; 32],
+    /// Program counter.
+    pc: usize,
+    /// Address of the instruction being executed.
+    instruction_pc: usize,
+    /// Contains program bytecode.
+    pub program: Vec<u8>,
+    /// Read-only data of the loaded executable.
+    ro_data: Vec<u8>,
+    /// Source locations of the loaded executable.
+    debug_info: DebugInfo,
+    /// Memory heap.
+    heap: Vec<u8>,
+    /// Stack of saved values and return addresses,
+    /// its length is the stack pointer.
+    stack: Vec<i32>,
+    /// Contains a remainder of module division operations.
+    remainder: u32,
+    /// Contains the result of the last comparison operation.
+    comparison_flag: bool,
+    /// Where the program prints to.
+    output: Output,
+    /// Where the program reads from.
+    input: Input,
+    /// Host functions called by `SYSCALL`, by their numbers.
+    syscalls: HashMap<u16, Box<dyn HostFunction>>,
+    /// Receives executed instructions, if tracing is enabled.
+    tracer: Option<Box<dyn Tracer>>,
+    /// Heap ranges written by the last host function.
+    host_writes: Vec<Range<usize>>,
+}
+
+impl VM {
+    /// Initializes a fresh VM state.
+    pub fn new() -> VM {
+        VM::default()
+    }
+
+    /// Replaces stdout as the destination of the program output.
+    pub fn with_output(mut self, output: impl Write + 'static) -> VM {
+        self.output = Output(Box::new(output));
+        self
+    }
+
+    /// Replaces stdin as the source of the program input.
+    pub fn with_input(mut self, input: impl Read + 'static) -> VM {
+        self.input = Input(Box::new(input));
+        self
+    }
+
+    /// Registers a host function under the number `SYSCALL` calls it by,
+    /// replacing the one registered before.
+    ///
+    /// The assembler only encodes numbers up to `i16::MAX` directly.
+    pub fn with_syscall(mut self, number: u16, function: impl HostFunction + 'static) -> VM {
+        self.syscalls.insert(number, Box::new(function));
+        self
+    }
+
+    /// Reports every executed instruction to the tracer.
+    pub fn with_tracer(mut self, tracer: impl Tracer + 'static) -> VM {
+        self.tracer = Some(Box::new(tracer));
+        self
+    }
+
+    pub fn add_byte(&mut self, byte: u8) {
+        self.program.push(byte);
+    }
+
+    pub fn add_bytes(&mut self, mut bytes: Vec<u8>) {
+        self.program.append(&mut bytes);
+    }
+
+    /// Validates a bytecode file and loads it for execution,
+    /// replacing the current program.
+    pub fn load(&mut self, bytes: &[u8]) -> Result<(), BytecodeError> {
+        let executable = Executable::from_bytes(bytes)?;
+        self.load_executable(executable);
+        Ok(())
+    }
+
+    /// Loads an already validated executable, replacing the current program.
+    pub fn load_executable(&mut self, executable: Executable) {
+        self.program = executable.code;
+        self.ro_data = executable.ro_data;
+        self.debug_info = executable.debug_info;
+        self.pc = executable.entry_point as usize;
+    }
+
+    /// Runs the VM until the program halts, runs out of
+    /// instructions or faults.
+    pub fn run(&mut self) -> Result<ExitReason, VmError> {
+        loop {
+            if let Some(reason) = self.execute_instruction()? {
+                return Ok(reason);
+            }
+        }
+    }
+
+    /// Performs a single step of VM execution.
+    ///
+    /// Returns `Some` exit reason if the program has stopped.
+    pub fn step(&mut self) -> Result<Option<ExitReason>, VmError> {
+        self.execute_instruction()
+    }
+
+    /// Performs a number of VM execution steps,
+    /// stopping early if the program stops.
+    pub fn step_times(&mut self, n: usize) -> Result<Option<ExitReason>, VmError> {
+        for _ in 0..n {
+            if let Some(reason) = self.execute_instruction()? {
+                return Ok(Some(reason));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Executes current VM instruction, tracing it if enabled.
+    fn execute_instruction(&mut self) -> Result<Option<ExitReason>, VmError> {
+        match self.tracer {
+            Some(_) => self.execute_traced(),
+            None => self.execute(),
+        }
+    }
+
+    /// Executes current VM instruction and reports its effects
+    /// to the tracer.
+    ///
+    /// Faulting instructions are reported as well, since host
+    /// functions may change the state before failing. Malformed
+    /// ones aren't, they are never executed.
+    fn execute_traced(&mut self) -> Result<Option<ExitReason>, VmError> {
+        let pc = self.pc;
+        let (opcode, operands) = match self.program.get(pc..).map(decode) {
+            Some(Ok(instruction)) => instruction,
+            _ => return self.execute(),
+        };
+        let registers = self.registers;
+        let float_registers = self.float_registers;
+        let comparison_flag = self.comparison_flag;
+        // Stores write a known range, host functions record theirs
+        let store = match opcode {
+            Opcode::STB => Some(1),
+            Opcode::STH => Some(2),
+            Opcode::STW => Some(4),
+            _ => None,
+        }
+        .and_then(|size| {
+            let address = self.heap_address(&operands, size).ok()?;
+            Some(address..address + size)
+        });
+        self.host_writes.clear();
+
+        let result = self.execute();
+        let heap_writes = match (store, opcode) {
+            (Some(range), _) if result.is_ok() => vec![HeapWrite {
+                address: range.start,
+                bytes: self.heap[range].to_vec(),
+            }],
+            (_, Opcode::SYSCALL) => trace::heap_writes(&self.heap, &self.host_writes),
+            _ => vec![],
+        };
+        let step = TraceStep {
+            pc,
+            opcode,
+            operands,
+            registers: trace::register_changes(&registers, &self.registers, |a, b| a == b),
+            float_registers: trace::register_changes(
+                &float_registers,
+                &self.float_registers,
+                |a, b| a.to_bits() == b.to_bits(),
+            ),
+            comparison_flag: Some((comparison_flag, self.comparison_flag))
+                .filter(|(old, new)| old != new),
+            heap_writes,
+        };
+        let traced = match &mut self.tracer {
+            Some(tracer) => tracer.trace(&step).map_err(io_error(pc)),
+            None => Ok(()),
+        };
+        let result = result?;
+        traced?;
+        Ok(result)
+    }
+
+    /// Executes current VM instruction.
+    ///
+    /// Operands are decoded according to the opcode table before
+    /// the instruction is executed, so a malformed instruction
+    /// never changes the VM state.
+    fn execute(&mut self) -> Result<Option<ExitReason>, VmError> {
+        if self.pc >= self.program.len() {
+            return Ok(Some(ExitReason::EndOfProgram));
+        }
+        self.instruction_pc = self.pc;
+        let opcode = self.decode_opcode();
+        if opcode == Opcode::IGL {
+            return Err(VmError::IllegalOpcode {
+                opcode: self.program[self.instruction_pc],
+                pc: self.instruction_pc,
+            });
+        }
+        let ops = self.decode_operands(opcode)?;
+        match opcode {
+            Opcode::NOP => {}
+            Opcode::LOAD => {
+                self.registers[ops.register(0)] = ops.immediate(1) as i32;
+            }
+            Opcode::LOADW => {
+                self.registers[ops.register(0)] = ops.word(1);
+            }
+            Opcode::ALLOC => {
+                let bytes = self.registers[ops.register(0)];
+                if bytes < 0 {
+                    return Err(VmError::InvalidAllocation {
+                        size: bytes,
+                        pc: self.instruction_pc,
+                    });
+                }
+                let size = self.heap.len() + bytes as usize;
+                if size > HEAP_LIMIT {
+                    return Err(VmError::HeapOverflow {
+                        requested: size,
+                        limit: HEAP_LIMIT,
+                        pc: self.instruction_pc,
+                    });
+                }
+                self.heap.resize(size, 0);
+            }
+            Opcode::ADD => self.arithmetic(&ops, i32::wrapping_add),
+            Opcode::SUB => self.arithmetic(&ops, i32::wrapping_sub),
+            Opcode::MUL => self.arithmetic(&ops, i32::wrapping_mul),
+            Opcode::DIV => {
+                let reg1 = self.registers[ops.register(0)];
+                let reg2 = self.registers[ops.register(1)];
+                if reg2 == 0 {
+                    return Err(VmError::DivisionByZero {
+                        pc: self.instruction_pc,
+                    });
+                }
+                self.registers[ops.register(2)] = reg1.wrapping_div(reg2);
+                self.remainder = reg1.wrapping_rem(reg2) as u32;
+            }
+            Opcode::AND => self.arithmetic(&ops, |a, b| a & b),
+            Opcode::OR => self.arithmetic(&ops, |a, b| a | b),
+            Opcode::XOR => self.arithmetic(&ops, |a, b| a ^ b),
+            Opcode::NOT => {
+                self.registers[ops.register(1)] = !self.registers[ops.register(0)];
+            }
+            // Shift amounts are taken modulo 32
+            Opcode::SHL => self.arithmetic(&ops, |a, b| a.wrapping_shl(b as u32)),
+            Opcode::SHR => self.arithmetic(&ops, |a, b| (a as u32).wrapping_shr(b as u32) as i32),
+            Opcode::SAR => self.arithmetic(&ops, |a, b| a.wrapping_shr(b as u32)),
+            Opcode::JMP => {
+                let target = self.registers[ops.register(0)];
+                self.jump(target as i64)?;
+            }
+            Opcode::JMPF => {
+                let offset = self.registers[ops.register(0)];
+                self.jump(self.pc as i64 + offset as i64)?;
+            }
+            Opcode::JMPB => {
+                let offset = self.registers[ops.register(0)];
+                self.jump(self.pc as i64 - offset as i64)?;
+            }
+            Opcode::EQ => self.compare(&ops, |a, b| a == b),
+            Opcode::NEQ => self.compare(&ops, |a, b| a != b),
+            Opcode::GT => self.compare(&ops, |a, b| a > b),
+            Opcode::LT => self.compare(&ops, |a, b| a < b),
+            Opcode::GTE => self.compare(&ops, |a, b| a >= b),
+            Opcode::LTE => self.compare(&ops, |a, b| a <= b),
+            Opcode::JEQ => {
+                let target = self.registers[ops.register(0)];
+                if self.comparison_flag {
+                    self.jump(target as i64)?;
+                }
+            }
+            Opcode::JNEQ => {
+                let target = self.registers[ops.register(0)];
+                if !self.comparison_flag {
+                    self.jump(target as i64)?;
+                }
+            }
+            Opcode::INC => {
+                let reg = ops.register(0);
+                self.registers[reg] = self.registers[reg].wrapping_add(1);
+            }
+            Opcode::DEC => {
+                let reg = ops.register(0);
+                self.registers[reg] = self.registers[reg].wrapping_sub(1);
+            }
+            Opcode::JMPI => self.jump(ops.address(0) as i64)?,
+            Opcode::JEQI => {
+                if self.comparison_flag {
+                    self.jump(ops.address(0) as i64)?;
+                }
+            }
+            Opcode::JNEQI => {
+                if !self.comparison_flag {
+                    self.jump(ops.address(0) as i64)?;
+                }
+            }
+            Opcode::LDB => {
+                let address = self.heap_address(&ops, 1)?;
+                self.registers[ops.register(0)] = self.heap[address] as i32;
+            }
+            Opcode::LDH => {
+                let address = self.heap_address(&ops, 2)?;
+                let bytes = [self.heap[address], self.heap[address + 1]];
+                self.registers[ops.register(0)] = u16::from_be_bytes(bytes) as i32;
+            }
+            Opcode::LDW => {
+                let address = self.heap_address(&ops, 4)?;
+                let mut bytes = [0; 4];
+                bytes.copy_from_slice(&self.heap[address..address + 4]);
+                self.registers[ops.register(0)] = i32::from_be_bytes(bytes);
+            }
+            Opcode::STB => {
+                let address = self.heap_address(&ops, 1)?;
+                self.heap[address] = self.registers[ops.register(0)] as u8;
+            }
+            Opcode::STH => {
+                let address = self.heap_address(&ops, 2)?;
+                let bytes = (self.registers[ops.register(0)] as u16).to_be_bytes();
+                self.heap[address..address + 2].copy_from_slice(&bytes);
+            }
+            Opcode::STW => {
+                let address = self.heap_address(&ops, 4)?;
+                let bytes = self.registers[ops.register(0)].to_be_bytes();
+                self.heap[address..address + 4].copy_from_slice(&bytes);
+            }
+            Opcode::CALL => {
+                let target = self.registers[ops.register(0)];
+                self.call(target as i64)?;
+            }
+            Opcode::CALLI => self.call(ops.address(0) as i64)?,
+            Opcode::RET => {
+                let target = self.pop()?;
+                self.jump(target as i64)?;
+            }
+            Opcode::PUSH => self.push(self.registers[ops.register(0)])?,
+            Opcode::POP => {
+                self.registers[ops.register(0)] = self.pop()?;
+            }
+            Opcode::LOADF64 => {
+                self.float_registers[ops.register(0)] = ops.float(1);
+            }
+            Opcode::ADDF64 => self.float_arithmetic(&ops, |a, b| a + b),
+            Opcode::SUBF64 => self.float_arithmetic(&ops, |a, b| a - b),
+            Opcode::MULF64 => self.float_arithmetic(&ops, |a, b| a * b),
+            Opcode::DIVF64 => self.float_arithmetic(&ops, |a, b| a / b),
+            Opcode::EQF64 => self.float_compare(&ops, |a, b| a == b),
+            Opcode::NEQF64 => self.float_compare(&ops, |a, b| a != b),
+            Opcode::GTF64 => self.float_compare(&ops, |a, b| a > b),
+            Opcode::GTEF64 => self.float_compare(&ops, |a, b| a >= b),
+            Opcode::LTF64 => self.float_compare(&ops, |a, b| a < b),
+            Opcode::LTEF64 => self.float_compare(&ops, |a, b| a <= b),
+            Opcode::ITOF => {
+                self.float_registers[ops.register(1)] = self.registers[ops.register(0)] as f64;
+            }
+            Opcode::FTOI => {
+                self.registers[ops.register(1)] = self.float_registers[ops.register(0)] as i32;
+            }
+            Opcode::PRTS => {
+                let address = self.registers[ops.register(0)] as i64;
+                self.print_string(address, false)?;
+            }
+            Opcode::PRTSI => self.print_string(ops.address(0) as i64, true)?,
+            Opcode::PRTI => {
+                let value = self.registers[ops.register(0)];
+                let pc = self.instruction_pc;
+                write!(self.output.0, "{}", value)
+                    .and_then(|_| self.output.0.flush())
+                    .map_err(io_error(pc))?;
+            }
+            Opcode::READ => {
+                self.registers[ops.register(0)] = self.read_byte()?;
+            }
+            Opcode::SYSCALL => self.syscall(ops.immediate(0) as u16)?,
+            Opcode::HLT => return Ok(Some(ExitReason::Halted)),
+            Opcode::IGL => unreachable!("illegal opcodes are rejected before decoding"),
+        }
+        Ok(None)
+    }
+
+    /// Applies an operation to two registers and
+    /// stores the result in the third one.
+    fn arithmetic(&mut self, ops: &Operands, op: impl Fn(i32, i32) -> i32) {
+        let reg1 = self.registers[ops.register(0)];
+        let reg2 = self.registers[ops.register(1)];
+        self.registers[ops.register(2)] = op(reg1, reg2);
+    }
+
+    /// Compares two registers and stores the result in the comparison flag.
+    fn compare(&mut self, ops: &Operands, op: impl Fn(i32, i32) -> bool) {
+        let reg1 = self.registers[ops.register(0)];
+        let reg2 = self.registers[ops.register(1)];
+        self.comparison_flag = op(reg1, reg2);
+    }
+
+    /// Same as `arithmetic`, but for float registers.
+    fn float_arithmetic(&mut self, ops: &Operands, op: impl Fn(f64, f64) -> f64) {
+        let reg1 = self.float_registers[ops.register(0)];
+        let reg2 = self.float_registers[ops.register(1)];
+        self.float_registers[ops.register(2)] = op(reg1, reg2);
+    }
+
+    /// Same as `compare`, but for float registers.
+    fn float_compare(&mut self, ops: &Operands, op: impl Fn(f64, f64) -> bool) {
+        let reg1 = self.float_registers[ops.register(0)];
+        let reg2 = self.float_registers[ops.register(1)];
+        self.comparison_flag = op(reg1, reg2);
+    }
+
+    /// Returns the address of the next instruction to execute.
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the stack pointer, i.e. a number of values on the stack.
+    pub fn sp(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns the allocated heap memory.
+    pub fn heap(&self) -> &[u8] {
+        &self.heap
+    }
+
+    pub fn debug_info(&self) -> &DebugInfo {
+        &self.debug_info
+    }
+
+    /// Returns the source location of the instruction at the address
+    /// as `file:line:column`, if the executable has debug info.
+    pub fn source_location(&self, pc: usize) -> Option<String> {
+        self.debug_info.format_location(pc as u32)
+    }
+
+    /// Pushes a return address and jumps to the subroutine.
+    fn call(&mut self, target: i64) -> Result<(), VmError> {
+        self.push(self.pc as i32)?;
+        self.jump(target)
+    }
+
+    fn push(&mut self, value: i32) -> Result<(), VmError> {
+        if self.stack.len() >= STACK_LIMIT {
+            return Err(VmError::StackOverflow {
+                pc: self.instruction_pc,
+            });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn pop(&mut self) -> Result<i32, VmError> {
+        self.stack.pop().ok_or(VmError::StackUnderflow {
+            pc: self.instruction_pc,
+        })
+    }
+
+    /// Moves the program counter to the given address.
+    ///
+    /// Jumping right past the last instruction is allowed
+    /// and simply ends the program.
+    fn jump(&mut self, target: i64) -> Result<(), VmError> {
+        if target < 0 || target > self.program.len() as i64 {
+            return Err(VmError::InvalidJump {
+                target,
+                pc: self.instruction_pc,
+            });
+        }
+        self.pc = target as usize;
+        Ok(())
+    }
+
+    /// Calls a registered host function.
+    fn syscall(&mut self, number: u16) -> Result<(), VmError> {
+        let pc = self.instruction_pc;
+        let function = self
+            .syscalls
+            .get_mut(&number)
+            .ok_or(VmError::UnknownSyscall { number, pc })?;
+        let mut context = HostContext {
+            registers: &mut self.registers,
+            float_registers: &mut self.float_registers,
+            heap: &mut self.heap,
+            writes: &mut self.host_writes,
+        };
+        function.call(&mut context).map_err(|e| VmError::Host {
+            number,
+            message: e.message().to_string(),
+            pc,
+        })
+    }
+
+    /// Prints a NUL-terminated string from either
+    /// the read-only data or the heap.
+    fn print_string(&mut self, address: i64, ro_data: bool) -> Result<(), VmError> {
+        let pc = self.instruction_pc;
+        let memory = if ro_data { &self.ro_data } else { &self.heap };
+        let bytes = match address {
+            address if address >= 0 => memory.get(address as usize..),
+            _ => None,
+        };
+        let string = bytes
+            .and_then(|bytes| Some(&bytes[..bytes.iter().position(|byte| *byte == 0)?]))
+            .ok_or(VmError::UnterminatedString { address, pc })?;
+        self.output
+            .0
+            .write_all(string)
+            .and_then(|_| self.output.0.flush())
+            .map_err(io_error(pc))
+    }
+
+    /// Reads a single byte of input, flushing the output first
+    /// so that prompts are shown. Returns -1 at the end of input.
+    fn read_byte(&mut self) -> Result<i32, VmError> {
+        let pc = self.instruction_pc;
+        self.output.0.flush().map_err(io_error(pc))?;
+        let mut byte = [0];
+        loop {
+            match self.input.0.read(&mut byte) {
+                Ok(0) => return Ok(-1),
+                Ok(_) => return Ok(byte[0] as i32),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(io_error(pc)(e)),
+            }
+        }
+    }
+
+    /// Computes a heap address of a load/store instruction from its
+    /// base address register and signed 16-bit offset operands,
+    /// checking that `size` bytes at that address are allocated.
+    fn heap_address(&self, ops: &Operands, size: usize) -> Result<usize, VmError> {
+        let base = self.registers[ops.register(1)];
+        let address = base as i64 + ops.immediate(2) as i64;
+        if address < 0 || address as usize + size > self.heap.len() {
+            return Err(VmError::HeapOutOfBounds {
+                address,
+                size,
+                pc: self.instruction_pc,
+            });
+        }
+        Ok(address as usize)
+    }
+
+    /// Decodes operands of the current instruction and
+    /// moves the program counter past them.
+    fn decode_operands(&mut self, opcode: Opcode) -> Result<Operands, VmError> {
+        let pc = self.instruction_pc;
+        let ops = opcode
+            .decode_operands(&self.program[self.pc..])
+            .map_err(|e| match e {
+                DecodeError::InvalidRegister(register) => VmError::InvalidRegister {
+                    register: register as usize,
+                    pc,
+                },
+                DecodeError::IllegalOpcode(opcode) => VmError::IllegalOpcode { opcode, pc },
+                DecodeError::Truncated => VmError::TruncatedInstruction { pc },
+            })?;
+        self.pc += opcode.info().instruction_len() - 1;
+        Ok(ops)
+    }
+
+    /// Decodes and returns a current `Opcode` and
+    /// increments a program counter.
+    fn decode_opcode(&mut self) -> Opcode {
+        let opcode = Opcode::from(self.program[self.pc]);
+        // Once we have decoded the opcode, we want to move the
+        // counter to the next byte
+        self.pc += 1;
+        opcode
+    }
+}
+
+/// Converts an I/O error of the instruction at `pc` into a VM fault.
+fn io_error(pc: usize) -> impl Fn(io::Error) -> VmError {
+    move |e| VmError::Io { kind: e.kind(), pc }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_vm() {
+        let vm = VM::new();
+        assert_eq!(vm.registers[0], 0);
+    }
+
+    #[test]
+    fn test_load_executable() {
+        let executable = Executable {
+            entry_point: 1,
+            code: vec![
+                Opcode::HLT.into(),
+                Opcode::INC.into(),
+                0,
+                Opcode::HLT.into(),
+            ],
+            ro_data: vec![7],
+            symbols: vec![],
+            debug_info: DebugInfo::default(),
+        };
+        let mut vm = VM::new();
+        vm.load(&executable.to_bytes()).unwrap();
+        assert_eq!(vm.pc, 1);
+        assert_eq!(vm.ro_data, vec![7]);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 1);
+
+        assert_eq!(vm.load(&[1, 2, 3]), Err(BytecodeError::BadMagic));
+    }
+
+    #[test]
+    fn test_opcode_load() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOAD.into(), 0, 1, 244];
+        // >> (1 as u16) << 8
+        // 256
+        // 244 =  11110100
+        // 256 = 100000000
+        // >> 256 | 244
+        // 500 = 111110100
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], 500);
+
+        // The immediate is sign-extended
+        vm.program = vec![Opcode::LOAD.into(), 0, 0xff, 0xff];
+        vm.pc = 0;
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], -1);
+    }
+
+    #[test]
+    fn test_opcode_loadw() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOADW.into(), 2];
+        vm.program.extend_from_slice(&70000i32.to_be_bytes());
+        vm.step().unwrap();
+        assert_eq!(vm.registers[2], 70000);
+    }
+
+    #[test]
+    fn test_opcode_alloc() {
+        let mut vm = VM::new();
+        vm.registers[0] = 1024;
+        vm.program = vec![Opcode::ALLOC.into(), 0, 0, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.heap.len(), 1024);
+    }
+
+    #[test]
+    fn test_opcode_add() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::ADD.into(), 0, 1, 2];
+        vm.registers[0] = 3;
+        vm.registers[1] = 4;
+        vm.step().unwrap();
+        assert_eq!(vm.registers[2], 7);
+    }
+
+    #[test]
+    fn test_opcode_sub() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::SUB.into(), 0, 1, 3];
+        vm.registers[0] = 10;
+        vm.registers[1] = 4;
+        vm.step().unwrap();
+        assert_eq!(vm.registers[3], 6);
+    }
+
+    #[test]
+    fn test_opcode_mul() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::MUL.into(), 0, 1, 0];
+        vm.registers[0] = 3;
+        vm.registers[1] = 4;
+        vm.step().unwrap();
+        assert_eq!(vm.registers[0], 12);
+    }
+
+    #[test]
+    fn test_opcode_div() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::DIV.into(), 0, 1, 3];
+        vm.registers[0] = 5;
+        vm.registers[1] = 2;
+        vm.step().unwrap();
+        assert_eq!(vm.registers[3], 2);
+        assert_eq!(vm.remainder, 1);
+    }
+
+    #[test]
+    fn test_opcode_and_or_xor() {
+        let mut vm = VM::new();
+        vm.registers[0] = 0b1100;
+        vm.registers[1] = 0b1010;
+        vm.program = [
+            [Opcode::AND.into(), 0, 1, 2],
+            [Opcode::OR.into(), 0, 1, 3],
+            [Opcode::XOR.into(), 0, 1, 4],
+        ]
+        .concat();
+        vm.run().unwrap();
+        assert_eq!(vm.registers[2], 0b1000);
+        assert_eq!(vm.registers[3], 0b1110);
+        assert_eq!(vm.registers[4], 0b0110);
+    }
+
+    #[test]
+    fn test_opcode_not() {
+        let mut vm = VM::new();
+        vm.registers[0] = 0x0f;
+        vm.program = vec![Opcode::NOT.into(), 0, 1];
+        vm.step().unwrap();
+        assert_eq!(vm.registers[1], !0x0f);
+    }
+
+    #[test]
+    fn test_opcode_shifts() {
+        let mut vm = VM::new();
+        vm.registers[0] = -16;
+        vm.registers[1] = 2;
+        vm.registers[2] = 33;
+        vm.program = [
+            [Opcode::SHL.into(), 0, 1, 3],
+            [Opcode::SHR.into(), 0, 1, 4],
+            [Opcode::SAR.into(), 0, 1, 5],
+            [Opcode::SHL.into(), 1, 2, 6],
+        ]
+        .concat();
+        vm.run().unwrap();
+        assert_eq!(vm.registers[3], -64);
+        assert_eq!(vm.registers[4], 0x3fff_fffc);
+        assert_eq!(vm.registers[5], -4);
+        assert_eq!(vm.registers[6], 4);
+    }
+
+    #[test]
+    fn test_opcode_loadf64() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOADF64.into(), 3];
+        vm.program.extend_from_slice(&2.5f64.to_be_bytes());
+        vm.step().unwrap();
+        assert_eq!(vm.float_registers[3], 2.5);
+    }
+
+    #[test]
+    fn test_opcode_float_arithmetic() {
+        let mut vm = VM::new();
+        vm.float_registers[0] = 7.5;
+        vm.float_registers[1] = 2.5;
+        vm.program = [
+            [Opcode::ADDF64.into(), 0, 1, 2],
+            [Opcode::SUBF64.into(), 0, 1, 3],
+            [Opcode::MULF64.into(), 0, 1, 4],
+            [Opcode::DIVF64.into(), 0, 1, 5],
+        ]
+        .concat();
+        vm.run().unwrap();
+        assert_eq!(vm.float_registers[2], 10.0);
+        assert_eq!(vm.float_registers[3], 5.0);
+        assert_eq!(vm.float_registers[4], 18.75);
+        assert_eq!(vm.float_registers[5], 3.0);
+    }
+
+    #[test]
+    fn test_opcode_float_comparisons() {
+        let mut vm = VM::new();
+        vm.float_registers[0] = 1.5;
+        vm.float_registers[1] = 2.0;
+        vm.program = vec![Opcode::LTF64.into(), 0, 1, Opcode::GTEF64.into(), 0, 1];
+        vm.step().unwrap();
+        assert!(vm.comparison_flag);
+        vm.step().unwrap();
+        assert!(!vm.comparison_flag);
+    }
+
+    #[test]
+    fn test_opcode_conversions() {
+        let mut vm = VM::new();
+        vm.registers[0] = -7;
+        vm.float_registers[1] = 3.99;
+        vm.program = vec![Opcode::ITOF.into(), 0, 0, Opcode::FTOI.into(), 1, 1];
+        vm.run().unwrap();
+        assert_eq!(vm.float_registers[0], -7.0);
+        assert_eq!(vm.registers[1], 3);
+    }
+
+    #[test]
+    fn test_opcode_jmp() {
+        let mut vm = VM::new();
+        vm.registers[0] = 1;
+        vm.program = vec![0, 0, Opcode::JMP.into(), 0, 0];
+        vm.step_times(3).unwrap();
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_opcode_jmpf() {
+        let mut vm = VM::new();
+        vm.registers[0] = 2;
+        vm.program = vec![Opcode::JMPF.into(), 0, 0, 0, 6, 0, 0, 0];
+        // pc  = 1 (in self.decode_opcode)
+        // pc += 1 (in self.next_8_bits)
+        vm.step().unwrap();
+        // pc += 2 (in JPMF opcode handler)
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_opcode_eq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 10;
+        vm.registers[1] = 10;
+        vm.program = vec![Opcode::EQ.into(), 0, 1, Opcode::EQ.into(), 0, 1];
+        vm.step().unwrap();
+        assert!(vm.comparison_flag);
+        vm.registers[1] = 20;
+        vm.step().unwrap();
+        assert!(!vm.comparison_flag);
+    }
+
+    #[test]
+    fn test_opcode_comparisons() {
+        let cases = [
+            (Opcode::NEQ, [false, true, true]),
+            (Opcode::GT, [false, true, false]),
+            (Opcode::LT, [false, false, true]),
+            (Opcode::GTE, [true, true, false]),
+            (Opcode::LTE, [true, false, true]),
+        ];
+        for (opcode, expected) in cases.iter() {
+            for (rhs, flag) in [5, -3, 8].iter().zip(expected) {
+                let mut vm = VM::new();
+                vm.registers[0] = 5;
+                vm.registers[1] = *rhs;
+                vm.program = vec![(*opcode).into(), 0, 1];
+                vm.step().unwrap();
+                assert_eq!(vm.comparison_flag, *flag, "{:?} 5 {}", opcode, rhs);
+                assert_eq!(vm.pc, 3);
+            }
+        }
+    }
+
+    #[test]
+    fn test_opcode_jeq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 7;
+        vm.comparison_flag = true;
+        vm.program = vec![Opcode::JEQ.into(), 0, 0, 0, 17, 0, 0, 0, 17, 0, 0, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 7);
+    }
+
+    #[test]
+    fn test_opcode_jneq() {
+        let mut vm = VM::new();
+        vm.registers[0] = 6;
+        vm.comparison_flag = true;
+        vm.program = vec![Opcode::JNEQ.into(), 0, Opcode::JNEQ.into(), 0, 0, 0, 0, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 2);
+        vm.comparison_flag = false;
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 6);
+    }
+
+    #[test]
+    fn test_opcode_jmpi() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::JMPI.into(), 0, 4, 0, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 4);
+    }
+
+    #[test]
+    fn test_opcode_jeqi() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::JEQI.into(), 0, 6, Opcode::JEQI.into(), 0, 6, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 3);
+        vm.comparison_flag = true;
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 6);
+    }
+
+    #[test]
+    fn test_opcode_jneqi() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::JNEQI.into(), 0, 5, 0, 0, 0];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 5);
+    }
+
+    #[test]
+    fn test_opcode_store_load() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 16];
+        vm.registers[0] = 4;
+        vm.registers[1] = -2;
+        vm.program = [
+            [Opcode::STW.into(), 1, 0, 0, 0],
+            [Opcode::STH.into(), 1, 0, 0, 8],
+            [Opcode::STB.into(), 1, 0, 0, 10],
+            [Opcode::LDW.into(), 2, 0, 0, 0],
+            [Opcode::LDH.into(), 3, 0, 0, 8],
+            [Opcode::LDB.into(), 4, 0, 0, 10],
+            [Opcode::LDB.into(), 5, 0, 255, 255],
+        ]
+        .concat();
+        vm.run().unwrap();
+        assert_eq!(&vm.heap[4..8], &[255, 255, 255, 254]);
+        assert_eq!(vm.registers[2], -2);
+        assert_eq!(vm.registers[3], 0xfffe);
+        assert_eq!(vm.registers[4], 0xfe);
+        assert_eq!(vm.registers[5], 0);
+    }
+
+    #[test]
+    fn test_heap_out_of_bounds() {
+        let mut vm = VM::new();
+        vm.heap = vec![0; 4];
+        vm.registers[0] = 2;
+        vm.program = vec![Opcode::LDW.into(), 1, 0, 0, 0];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::HeapOutOfBounds {
+                address: 2,
+                size: 4,
+                pc: 0
+            })
+        );
+
+        vm.pc = 0;
+        vm.program = vec![Opcode::STB.into(), 1, 0, 255, 253];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::HeapOutOfBounds {
+                address: -1,
+                size: 1,
+                pc: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_opcode_call_ret() {
+        let mut vm = VM::new();
+        vm.program = vec![
+            Opcode::CALLI.into(),
+            0,
+            4,
+            Opcode::HLT.into(),
+            Opcode::INC.into(),
+            0,
+            Opcode::RET.into(),
+        ];
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 4);
+        assert_eq!(vm.stack, vec![3]);
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+        assert_eq!(vm.registers[0], 1);
+        assert_eq!(vm.sp(), 0);
+    }
+
+    #[test]
+    fn test_opcode_push_pop() {
+        let mut vm = VM::new();
+        vm.registers[0] = 42;
+        vm.program = vec![Opcode::PUSH.into(), 0, Opcode::POP.into(), 1];
+        vm.step().unwrap();
+        assert_eq!(vm.sp(), 1);
+        vm.step().unwrap();
+        assert_eq!(vm.sp(), 0);
+        assert_eq!(vm.registers[1], 42);
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::RET.into()];
+        assert_eq!(vm.run(), Err(VmError::StackUnderflow { pc: 0 }));
+
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::PUSH.into(), 0, Opcode::JMPI.into(), 0, 0];
+        assert_eq!(vm.run(), Err(VmError::StackOverflow { pc: 0 }));
+        assert_eq!(vm.sp(), STACK_LIMIT);
+    }
+
+    #[test]
+    fn test_opcode_print() {
+        let output = OutputBuffer::new();
+        let mut vm = VM::new().with_output(output.clone());
+        vm.ro_data = b"Hi\0".to_vec();
+        vm.heap = b"?!\0".to_vec();
+        vm.registers[0] = 1;
+        vm.registers[1] = -42;
+        vm.program = vec![
+            Opcode::PRTSI.into(),
+            0,
+            0,
+            Opcode::PRTS.into(),
+            0,
+            Opcode::PRTI.into(),
+            1,
+        ];
+        assert_eq!(vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(output.text(), "Hi!-42");
+    }
+
+    #[test]
+    fn test_print_errors() {
+        let mut vm = VM::new().with_output(io::sink());
+        vm.heap = b"abc".to_vec();
+        vm.program = vec![Opcode::PRTS.into(), 0];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnterminatedString { address: 0, pc: 0 })
+        );
+
+        vm.registers[0] = -1;
+        vm.pc = 0;
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnterminatedString { address: -1, pc: 0 })
+        );
+
+        let mut vm = VM::new().with_output(io::sink());
+        vm.program = vec![Opcode::PRTSI.into(), 0, 5];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnterminatedString { address: 5, pc: 0 })
+        );
+    }
+
+    #[test]
+    fn test_opcode_read() {
+        let mut vm = VM::new().with_input(&b"a"[..]);
+        vm.program = vec![Opcode::READ.into(), 0, Opcode::READ.into(), 1];
+        assert_eq!(vm.run(), Ok(ExitReason::EndOfProgram));
+        assert_eq!(vm.registers[..2], [b'a' as i32, -1]);
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut vm = VM::new();
+        let test_bytes = vec![Opcode::HLT.into(), 0, 0, 0];
+        vm.program = test_bytes;
+        vm.step().unwrap();
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut vm = VM::new();
+        let test_bytes = vec![200, 0, 0, 0];
+        vm.program = test_bytes;
+        let result = vm.step();
+        assert_eq!(result, Err(VmError::IllegalOpcode { opcode: 200, pc: 0 }));
+        assert_eq!(vm.pc, 1);
+    }
+
+    #[test]
+    fn test_instruction_length() {
+        use crate::instruction::OPCODES;
+
+        let skipped = [
+            Opcode::JMPF,
+            Opcode::JMPB,
+            Opcode::DIV,
+            Opcode::RET,
+            Opcode::POP,
+            Opcode::HLT,
+            Opcode::IGL,
+        ];
+        for info in OPCODES {
+            if skipped.contains(&info.opcode) {
+                continue;
+            }
+            let mut vm = VM::new()
+                .with_input(io::empty())
+                .with_output(io::sink())
+                .with_syscall(0, |_: &mut HostContext| Ok(()));
+            vm.heap = vec![0; 16];
+            vm.ro_data = vec![0; 16];
+            // Make every absolute jump target the next instruction
+            vm.registers[0] = info.instruction_len() as i32;
+            vm.program = vec![info.code];
+            vm.program.resize(info.instruction_len(), 0);
+            if info
+                .operands
+                .contains(&crate::instruction::OperandKind::Address)
+            {
+                vm.program[2] = info.instruction_len() as u8;
+            }
+            vm.step().unwrap();
+            assert_eq!(vm.pc, info.instruction_len(), "{:?}", info.opcode);
+        }
+    }
+
+    #[test]
+    fn test_run_exit_reason() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::HLT.into(), 0, 0, 0];
+        assert_eq!(vm.run(), Ok(ExitReason::Halted));
+
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::NOP.into(), Opcode::NOP.into()];
+        assert_eq!(vm.run(), Ok(ExitReason::EndOfProgram));
+    }
+
+    #[test]
+    fn test_div_by_zero() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::NOP.into(), Opcode::DIV.into(), 0, 1, 2];
+        vm.registers[0] = 5;
+        assert_eq!(vm.run(), Err(VmError::DivisionByZero { pc: 1 }));
+    }
+
+    #[test]
+    fn test_invalid_register() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::INC.into(), 32];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::InvalidRegister {
+                register: 32,
+                pc: 0
+            })
+        );
+    }
+
+    #[test]
+    fn test_truncated_instruction() {
+        let mut vm = VM::new();
+        vm.program = vec![Opcode::LOAD.into(), 0, 1];
+        assert_eq!(vm.run(), Err(VmError::TruncatedInstruction { pc: 0 }));
+    }
+
+    #[test]
+    fn test_invalid_jump() {
+        let mut vm = VM::new();
+        vm.registers[0] = 10;
+        vm.program = vec![Opcode::JMPB.into(), 0];
+        assert_eq!(vm.run(), Err(VmError::InvalidJump { target: -8, pc: 0 }));
+    }
+
+    #[test]
+    fn test_heap_overflow() {
+        let mut vm = VM::new();
+        vm.registers[0] = HEAP_LIMIT as i32 + 1;
+        vm.program = vec![Opcode::ALLOC.into(), 0];
+        assert_eq!(
+            vm.run(),
+            Err(VmError::HeapOverflow {
+                requested: HEAP_LIMIT + 1,
+                limit: HEAP_LIMIT,
+                pc: 0
+            })
+        );
+
+        vm.registers[0] = -1;
+        vm.pc = 0;
+        assert_eq!(
+            vm.run(),
+            Err(VmError::InvalidAllocation { size: -1, pc: 0 })
+        );
+    }
+}
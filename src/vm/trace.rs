@@ -0,0 +1,304 @@
+use crate::disassembler::format_instruction;
+use crate::instruction::{Opcode, Operands};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::ops::Range;
+
+/// Receives every instruction the VM executes along with its effects.
+///
+/// The VM only computes the effects when a tracer is set,
+/// so running without one costs nothing.
+pub trait Tracer {
+    fn trace(&mut self, step: &TraceStep) -> io::Result<()>;
+}
+
+impl<T: Tracer + ?Sized> Tracer for Box<T> {
+    fn trace(&mut self, step: &TraceStep) -> io::Result<()> {
+        (**self).trace(step)
+    }
+}
+
+/// Executed instruction and the state it has changed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceStep {
+    /// Address of the instruction.
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub operands: Operands,
+    pub registers: Vec<RegisterChange<i32>>,
+    pub float_registers: Vec<RegisterChange<f64>>,
+    /// Old and new values of the comparison flag, if it has changed.
+    pub comparison_flag: Option<(bool, bool)>,
+    pub heap_writes: Vec<HeapWrite>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange<T> {
+    pub register: usize,
+    pub old: T,
+    pub new: T,
+}
+
+/// Bytes written to the heap, whether or not they differ
+/// from the previous contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeapWrite {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+}
+
+impl TraceStep {
+    /// Formats the instruction as the disassembler does, without labels.
+    pub fn instruction(&self) -> String {
+        format_instruction(self.opcode, &self.operands, &HashMap::new())
+    }
+}
+
+/// Returns the registers that differ between the two states.
+pub(super) fn register_changes<T: Copy>(
+    old: &[T],
+    new: &[T],
+    same: impl Fn(T, T) -> bool,
+) -> Vec<RegisterChange<T>> {
+    old.iter()
+        .zip(new)
+        .enumerate()
+        .filter(|(_, (&old, &new))| !same(old, new))
+        .map(|(register, (&old, &new))| RegisterChange { register, old, new })
+        .collect()
+}
+
+/// Returns the contents of the written heap ranges,
+/// merging the ones that overlap or touch.
+pub(super) fn heap_writes(heap: &[u8], ranges: &[Range<usize>]) -> Vec<HeapWrite> {
+    let mut ranges: Vec<_> = ranges.iter().filter(|r| !r.is_empty()).cloned().collect();
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = vec![];
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+        .into_iter()
+        .map(|range| HeapWrite {
+            address: range.start,
+            bytes: heap[range].to_vec(),
+        })
+        .collect()
+}
+
+/// Writes a human-readable line per instruction.
+pub struct TextTracer<W> {
+    output: W,
+}
+
+impl<W: Write> TextTracer<W> {
+    pub fn new(output: W) -> TextTracer<W> {
+        TextTracer { output }
+    }
+}
+
+impl<W: Write> Tracer for TextTracer<W> {
+    fn trace(&mut self, step: &TraceStep) -> io::Result<()> {
+        let mut changes = vec![];
+        for change in &step.registers {
+            changes.push(format!(
+                "${}: {} -> {}",
+                change.register, change.old, change.new
+            ));
+        }
+        for change in &step.float_registers {
+            changes.push(format!(
+                "float ${}: {:?} -> {:?}",
+                change.register, change.old, change.new
+            ));
+        }
+        if let Some((old, new)) = step.comparison_flag {
+            changes.push(format!("flag: {} -> {}", old, new));
+        }
+        for write in &step.heap_writes {
+            let mut text = format!(
+                "heap[{}..{}] =",
+                write.address,
+                write.address + write.bytes.len()
+            );
+            for byte in &write.bytes {
+                write!(text, " {:02x}", byte).unwrap();
+            }
+            changes.push(text);
+        }
+        match changes.is_empty() {
+            true => writeln!(self.output, "{:>6}: {}", step.pc, step.instruction()),
+            false => writeln!(
+                self.output,
+                "{:>6}: {} ; {}",
+                step.pc,
+                step.instruction(),
+                changes.join(", ")
+            ),
+        }
+    }
+}
+
+/// Writes a JSON object per instruction, one per line.
+///
+/// Non-finite float register values are written as `null`.
+pub struct JsonTracer<W> {
+    output: W,
+}
+
+impl<W: Write> JsonTracer<W> {
+    pub fn new(output: W) -> JsonTracer<W> {
+        JsonTracer { output }
+    }
+}
+
+impl<W: Write> Tracer for JsonTracer<W> {
+    fn trace(&mut self, step: &TraceStep) -> io::Result<()> {
+        let registers: Vec<_> = step
+            .registers
+            .iter()
+            .map(|c| json!({ "register": c.register, "old": c.old, "new": c.new }))
+            .collect();
+        let float_registers: Vec<_> = step
+            .float_registers
+            .iter()
+            .map(|c| json!({ "register": c.register, "old": c.old, "new": c.new }))
+            .collect();
+        let heap_writes: Vec<_> = step
+            .heap_writes
+            .iter()
+            .map(|w| json!({ "address": w.address, "bytes": w.bytes }))
+            .collect();
+        let line = json!({
+            "pc": step.pc,
+            "instruction": step.instruction(),
+            "registers": registers,
+            "float_registers": float_registers,
+            "comparison_flag": step.comparison_flag.map(|(old, new)| json!({ "old": old, "new": new })),
+            "heap_writes": heap_writes,
+        });
+        writeln!(self.output, "{}", line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::{HostContext, HostError, OutputBuffer, VM};
+
+    const PROGRAM: &str = "
+        load $0 #8
+        alloc $0
+        load $1 #2
+        stw $0 $1 #0
+        lt $1 $0
+        loadf64 $1 #1.5
+        hlt
+    ";
+
+    fn run(vm: VM, source: &str) {
+        let mut vm = vm;
+        vm.load_executable(Assembler::new().assemble(source).unwrap());
+        vm.run().unwrap();
+    }
+
+    #[test]
+    fn test_text_tracer() {
+        let output = OutputBuffer::new();
+        run(
+            VM::new().with_tracer(TextTracer::new(output.clone())),
+            PROGRAM,
+        );
+        assert_eq!(
+            output.text(),
+            "     0: load $0 #8 ; $0: 0 -> 8
+     4: alloc $0
+     6: load $1 #2 ; $1: 0 -> 2
+    10: stw $0 $1 #0 ; heap[2..6] = 00 00 00 08
+    15: lt $1 $0 ; flag: false -> true
+    18: loadf64 $1 #1.5 ; float $1: 0.0 -> 1.5
+    28: hlt
+"
+        );
+    }
+
+    #[test]
+    fn test_json_tracer() {
+        let output = OutputBuffer::new();
+        run(
+            VM::new().with_tracer(JsonTracer::new(output.clone())),
+            PROGRAM,
+        );
+        let lines: Vec<serde_json::Value> = output
+            .text()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 7);
+        assert_eq!(
+            lines[0],
+            json!({
+                "pc": 0,
+                "instruction": "load $0 #8",
+                "registers": [{ "register": 0, "old": 0, "new": 8 }],
+                "float_registers": [],
+                "comparison_flag": null,
+                "heap_writes": [],
+            })
+        );
+        assert_eq!(
+            lines[3]["heap_writes"],
+            json!([{ "address": 2, "bytes": [0, 0, 0, 8] }])
+        );
+        assert_eq!(
+            lines[4]["comparison_flag"],
+            json!({ "old": false, "new": true })
+        );
+        assert_eq!(
+            lines[5]["float_registers"],
+            json!([{ "register": 1, "old": 0.0, "new": 1.5 }])
+        );
+    }
+
+    #[test]
+    fn test_syscall_heap_writes() {
+        let output = OutputBuffer::new();
+        let fill = |context: &mut HostContext| -> Result<(), HostError> {
+            let address = context.alloc(8)?;
+            context
+                .heap_mut(address + 1..address + 3)?
+                .copy_from_slice(&[1, 2]);
+            context.heap_mut(address + 2..address + 4)?[1] = 3;
+            context.heap_mut(address + 6..address + 7)?[0] = 4;
+            context.registers[0] = address as i32;
+            Ok(())
+        };
+        let fail = |context: &mut HostContext| -> Result<(), HostError> {
+            context.heap_mut(0..1)?[0] = 9;
+            context.registers[1] = 5;
+            Err(HostError::new("Failed"))
+        };
+        let mut vm = VM::new()
+            .with_syscall(0, fill)
+            .with_syscall(1, fail)
+            .with_tracer(TextTracer::new(output.clone()));
+        vm.load_executable(
+            Assembler::new()
+                .assemble("syscall #0\nsyscall #1\n")
+                .unwrap(),
+        );
+        assert!(vm.run().is_err());
+        assert_eq!(
+            output.text(),
+            "     0: syscall #0 ; heap[1..4] = 01 02 03, heap[6..7] = 04
+     3: syscall #1 ; $1: 0 -> 5, heap[0..1] = 09
+"
+        );
+    }
+}